@@ -0,0 +1,507 @@
+//! Endpoints used to manage this server. Every one of them requires an [AdminAuth]
+
+use std::future::ready;
+use std::future::Ready;
+
+use actix_web::delete;
+use actix_web::get;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::post;
+use actix_web::put;
+use actix_web::web;
+use actix_web::FromRequest;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+
+use super::config::ServerConfig;
+use super::error::ApiError;
+use super::lnaddress::load_user;
+use super::lnaddress::validate_username;
+use super::lnurl::LnUrls;
+use crate::db::Payment;
+use crate::db::PaymentFilter;
+use crate::nostr::nip98::validate_auth_header;
+use crate::nostr::zap_handler::now;
+use crate::users::PayerDataSettings;
+use crate::users::SuccessAction;
+use crate::users::UserData;
+
+/// Proof that a request came from an admin
+///
+/// Admins authenticate with NIP-98, signing an auth event for each request with one of the keys
+/// given with `--admin`. If we don't have any, nobody is an admin.
+///
+/// As an extractor, this only works for requests without a body. Endpoints that take one read
+/// it as bytes and call [AdminAuth::authenticate] themselves, so the auth event must commit to
+/// it.
+pub struct AdminAuth;
+
+impl AdminAuth {
+    fn authenticate(req: &HttpRequest, body: Option<&[u8]>) -> Result<Self, ApiError> {
+        let config = req
+            .app_data::<web::Data<ServerConfig>>()
+            .expect("we always have a config");
+
+        let header = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|header| header.to_str().ok())
+            .ok_or(ApiError::Unauthorized)?;
+
+        // clients sign the url they see, which is our public url
+        let url = format!("{}{}", config.base_url(req), req.uri());
+        let admin = validate_auth_header(
+            header,
+            &url,
+            req.method().as_str(),
+            body,
+            now(),
+            &config.seen_auth_events,
+        )?;
+        if !config.admins.contains(&admin) {
+            return Err(ApiError::Unauthorized);
+        }
+
+        Ok(AdminAuth)
+    }
+}
+
+impl FromRequest for AdminAuth {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(Self::authenticate(req, None))
+    }
+}
+
+#[derive(Serialize)]
+/// A page of our payment ledger
+pub struct PaymentsResponse {
+    /// The payments in this page, newest first
+    payments: Vec<Payment>,
+    /// How many payments match the filter, across all pages
+    total: u64,
+}
+
+#[get("/admin/payments")]
+pub async fn list_payments(
+    _auth: AdminAuth,
+    filter: web::Query<PaymentFilter>,
+    config: web::Data<ServerConfig>,
+) -> Result<impl Responder, ApiError> {
+    let (payments, total) = config.db.list_payments(&filter)?;
+
+    Ok(HttpResponse::Ok().json(PaymentsResponse { payments, total }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// What an admin sends us to create or edit a user. The metadata is built from these
+pub struct UserRequest {
+    /// A short description, shown by wallets before paying
+    description: String,
+    /// A longer description, if wallets want to show one
+    long_description: Option<String>,
+    /// A base64-encoded png or jpeg image
+    image: Option<String>,
+    /// This user's nostr public key, either hex-encoded or as an npub. Only needed for zaps
+    nostr_pubkey: Option<String>,
+    /// How long the comments sent with a payment can be. Zero (the default) disables them
+    #[serde(default)]
+    comment_allowed: u32,
+    /// Which information payers should send about themselves
+    payer_data: Option<PayerDataSettings>,
+    /// What the payer's wallet should show after paying
+    success_action: Option<SuccessAction>,
+    /// The smallest amount this user can receive, in milisats
+    min_sendable: Option<u64>,
+    /// The biggest amount this user can receive, in milisats
+    max_sendable: Option<u64>,
+}
+
+impl UserRequest {
+    /// Reads a request from its json body
+    fn parse(body: &[u8]) -> Result<Self, ApiError> {
+        serde_json::from_slice(body).map_err(|e| ApiError::InvalidUser(e.to_string()))
+    }
+
+    /// Builds a user out of this request, with the LUD-06 metadata wallets expect
+    fn into_user(
+        self,
+        username: &str,
+        config: &ServerConfig,
+        req: &HttpRequest,
+    ) -> Result<UserData, ApiError> {
+        // LUD-16 wants the lightning address itself in the metadata
+        let mut metadata = vec![
+            ["text/plain".to_owned(), self.description],
+            [
+                "text/identifier".to_owned(),
+                format!("{username}@{}", config.domain(req)),
+            ],
+        ];
+
+        if let Some(long_description) = self.long_description {
+            metadata.push(["text/long-desc".to_owned(), long_description]);
+        }
+
+        if let Some(image) = self.image {
+            let bytes = BASE64_STANDARD
+                .decode(&image)
+                .map_err(|_| ApiError::InvalidImage)?;
+            let mime = match bytes.as_slice() {
+                [0x89, b'P', b'N', b'G', ..] => "image/png;base64",
+                [0xff, 0xd8, 0xff, ..] => "image/jpeg;base64",
+                _ => return Err(ApiError::InvalidImage),
+            };
+
+            metadata.push([mime.to_owned(), image]);
+        }
+
+        let user = UserData {
+            metadata: serde_json::to_string(&metadata)?,
+            nostr_pubkey: self.nostr_pubkey,
+            comment_allowed: self.comment_allowed,
+            payer_data: self.payer_data,
+            success_action: self.success_action,
+            min_sendable: self.min_sendable,
+            max_sendable: self.max_sendable,
+        };
+        user.validate()
+            .and_then(|_| user.sendable(config.min_sendable, config.max_sendable))
+            .map_err(|e| ApiError::InvalidUser(e.to_string()))?;
+
+        Ok(user)
+    }
+}
+
+#[derive(Serialize)]
+/// A user, as returned by the admin API
+pub struct UserResponse {
+    username: String,
+    #[serde(flatten)]
+    user: UserData,
+    /// So admins can hand out QR codes to wallets without lightning address support
+    #[serde(flatten)]
+    lnurls: LnUrls,
+}
+
+impl UserResponse {
+    fn new(username: String, user: UserData, base_url: &str) -> Self {
+        let lnurls = LnUrls::new(base_url, &username);
+        UserResponse {
+            username,
+            user,
+            lnurls,
+        }
+    }
+}
+
+#[derive(Serialize)]
+/// Every user we have, as returned by the admin API
+pub struct UsersResponse {
+    /// Our users, sorted by username
+    users: Vec<UserResponse>,
+}
+
+#[get("/admin/users")]
+pub async fn list_users(
+    _auth: AdminAuth,
+    req: HttpRequest,
+    config: web::Data<ServerConfig>,
+) -> Result<impl Responder, ApiError> {
+    let base_url = config.base_url(&req);
+    let users = config
+        .users
+        .list()?
+        .into_iter()
+        .map(|(username, user)| UserResponse::new(username, user, &base_url))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(UsersResponse { users }))
+}
+
+#[post("/admin/users/{user}")]
+pub async fn create_user(
+    user: web::Path<String>,
+    body: web::Bytes,
+    req: HttpRequest,
+    config: web::Data<ServerConfig>,
+) -> Result<impl Responder, ApiError> {
+    AdminAuth::authenticate(&req, Some(&body))?;
+    let request = UserRequest::parse(&body)?;
+    let username = user.into_inner();
+    validate_username(&username)?;
+
+    let user = request.into_user(&username, &config, &req)?;
+    if !config.users.insert(&username, &user)? {
+        return Err(ApiError::UserAlreadyExists);
+    }
+
+    Ok(HttpResponse::Created().json(UserResponse::new(username, user, &config.base_url(&req))))
+}
+
+#[get("/admin/users/{user}")]
+pub async fn get_user(
+    _auth: AdminAuth,
+    user: web::Path<String>,
+    req: HttpRequest,
+    config: web::Data<ServerConfig>,
+) -> Result<impl Responder, ApiError> {
+    let username = user.into_inner();
+    let user = load_user(config.users.as_ref(), &username)?;
+
+    Ok(HttpResponse::Ok().json(UserResponse::new(username, user, &config.base_url(&req))))
+}
+
+#[put("/admin/users/{user}")]
+pub async fn update_user(
+    user: web::Path<String>,
+    body: web::Bytes,
+    req: HttpRequest,
+    config: web::Data<ServerConfig>,
+) -> Result<impl Responder, ApiError> {
+    AdminAuth::authenticate(&req, Some(&body))?;
+    let request = UserRequest::parse(&body)?;
+    let username = user.into_inner();
+    load_user(config.users.as_ref(), &username)?;
+
+    let user = request.into_user(&username, &config, &req)?;
+    config.users.put(&username, &user)?;
+
+    Ok(HttpResponse::Ok().json(UserResponse::new(username, user, &config.base_url(&req))))
+}
+
+#[delete("/admin/users/{user}")]
+pub async fn delete_user(
+    _auth: AdminAuth,
+    user: web::Path<String>,
+    config: web::Data<ServerConfig>,
+) -> Result<impl Responder, ApiError> {
+    let username = user.into_inner();
+    validate_username(&username)?;
+    if !config.users.delete(&username)? {
+        return Err(ApiError::UnknownUser);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::web::Data;
+    use actix_web::App;
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine;
+    use hex_conservative::DisplayHex;
+    use secp256k1::Secp256k1;
+    use secp256k1::SecretKey;
+    use serde_json::json;
+    use serde_json::Value;
+    use sha2::Digest;
+    use sha2::Sha256;
+
+    use super::create_user;
+    use super::delete_user;
+    use super::get_user;
+    use super::list_users;
+    use super::update_user;
+    use crate::api::config::ServerConfig;
+    use crate::db::Database;
+    use crate::nostr::nostr_event::UnsignedEvent;
+    use crate::nostr::zap_handler::now;
+    use crate::users::sqlite::SqliteUserStore;
+
+    fn admin_key() -> SecretKey {
+        SecretKey::from_str("d7bee682d987439fae91bdc5fed8bbf16d84ec077a2bd5cf7592e384668198f3")
+            .unwrap()
+    }
+
+    /// A NIP-98 header for a request to `path`. Each one is a new event, so they aren't replays
+    fn auth_header(sec_key: &SecretKey, method: &str, path: &str, body: Option<&str>) -> String {
+        let mut tags = vec![
+            vec!["u".into(), format!("https://smith.com{path}")],
+            vec!["method".into(), method.into()],
+        ];
+        if let Some(body) = body {
+            tags.push(vec![
+                "payload".into(),
+                Sha256::digest(body).to_lower_hex_string(),
+            ]);
+        }
+
+        let event = UnsignedEvent {
+            pubkey: sec_key.x_only_public_key(&Secp256k1::new()).0.to_string(),
+            created_at: now(),
+            kind: 27235,
+            tags,
+            content: rand::random::<u64>().to_string(),
+        }
+        .into_signed(sec_key);
+
+        format!(
+            "Nostr {}",
+            BASE64_STANDARD.encode(serde_json::to_string(&event).unwrap())
+        )
+    }
+
+    #[actix_web::test]
+    async fn test_users() {
+        let users = Arc::new(SqliteUserStore::new(Database::open(":memory:").unwrap()));
+        let admin = admin_key().x_only_public_key(&Secp256k1::new()).0;
+        let app = test::init_service(
+            App::new()
+                .service(list_users)
+                .service(create_user)
+                .service(get_user)
+                .service(update_user)
+                .service(delete_user)
+                .app_data(Data::new(ServerConfig::for_tests(users, vec![admin]))),
+        )
+        .await;
+
+        let request = |method: &str, path: &str, body: Option<&str>, auth: Option<String>| {
+            let req = match method {
+                "GET" => test::TestRequest::get(),
+                "POST" => test::TestRequest::post(),
+                "PUT" => test::TestRequest::put(),
+                _ => test::TestRequest::delete(),
+            };
+            let req = req.uri(path).set_payload(body.unwrap_or("").to_owned());
+            match auth {
+                Some(auth) => req.insert_header((AUTHORIZATION, auth)).to_request(),
+                None => req.to_request(),
+            }
+        };
+        let admin_request = |method: &str, path: &str, body: Option<&str>| {
+            request(
+                method,
+                path,
+                body,
+                Some(auth_header(&admin_key(), method, path, body)),
+            )
+        };
+
+        let body = json!({
+            "description": "john's address",
+            "image": "iVBORw0KGgo=",
+            "commentAllowed": 100,
+        })
+        .to_string();
+
+        // nobody else can manage users
+        let res = test::call_service(
+            &app,
+            request("POST", "/admin/users/john", Some(&body), None),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let stranger =
+            SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000002")
+                .unwrap();
+        let auth = auth_header(&stranger, "POST", "/admin/users/john", Some(&body));
+        let res = test::call_service(
+            &app,
+            request("POST", "/admin/users/john", Some(&body), Some(auth)),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // the auth event must commit to the body
+        let auth = auth_header(&admin_key(), "POST", "/admin/users/john", Some("{}"));
+        let res = test::call_service(
+            &app,
+            request("POST", "/admin/users/john", Some(&body), Some(auth)),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = test::call_service(
+            &app,
+            admin_request("POST", "/admin/users/john", Some(&body)),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let john: Value = test::read_body_json(res).await;
+        let metadata: Value = serde_json::from_str(john["metadata"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            metadata,
+            json!([
+                ["text/plain", "john's address"],
+                ["text/identifier", "john@smith.com"],
+                ["image/png;base64", "iVBORw0KGgo="],
+            ])
+        );
+        assert_eq!(john["commentAllowed"], 100);
+        assert_eq!(john["lnurlp"], "lnurlp://smith.com/.well-known/lnurlp/john");
+
+        let res = test::call_service(
+            &app,
+            admin_request("POST", "/admin/users/john", Some(&body)),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let gif = json!({"description": "john", "image": "R0lGODlh"}).to_string();
+        let res =
+            test::call_service(&app, admin_request("POST", "/admin/users/mary", Some(&gif))).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // our global minimum is 1 sat, so this user couldn't receive anything
+        let unpayable = json!({"description": "mary", "maxSendable": 500}).to_string();
+        let res = test::call_service(
+            &app,
+            admin_request("POST", "/admin/users/mary", Some(&unpayable)),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = test::call_service(&app, admin_request("GET", "/admin/users/john", None)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = test::call_service(&app, request("GET", "/admin/users/john", None, None)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let update = json!({"description": "john, updated"}).to_string();
+        let res = test::call_service(
+            &app,
+            admin_request("PUT", "/admin/users/john", Some(&update)),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = test::call_service(
+            &app,
+            admin_request("PUT", "/admin/users/mary", Some(&update)),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = test::call_service(&app, admin_request("GET", "/admin/users", None)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let list: Value = test::read_body_json(res).await;
+        let users = list["users"].as_array().unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0]["username"], "john");
+        assert!(users[0]["metadata"]
+            .as_str()
+            .unwrap()
+            .contains("john, updated"));
+
+        let res =
+            test::call_service(&app, admin_request("DELETE", "/admin/users/john", None)).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res =
+            test::call_service(&app, admin_request("DELETE", "/admin/users/john", None)).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,11 +1,21 @@
 use actix_cors::Cors;
 use actix_web::web::Data;
+use actix_web::web::PayloadConfig;
 use actix_web::App;
 use actix_web::HttpServer;
 
+use super::admin::create_user;
+use super::admin::delete_user;
+use super::admin::get_user;
+use super::admin::list_payments;
+use super::admin::list_users;
+use super::admin::update_user;
 use super::callback::ln_url_callback;
 use super::config::ServerConfig;
 use super::lnaddress::well_known;
+use super::lnurl::lnurl;
+use super::nip05::nostr_json;
+use super::verify::verify;
 
 /// Actually runs the server
 pub async fn run_server(config: ServerConfig) -> std::io::Result<()> {
@@ -16,7 +26,19 @@ pub async fn run_server(config: ServerConfig) -> std::io::Result<()> {
             .wrap(cors)
             .service(ln_url_callback)
             .service(well_known)
+            .service(nostr_json)
+            .service(lnurl)
+            .service(verify)
+            .service(list_payments)
+            .service(list_users)
+            .service(create_user)
+            .service(get_user)
+            .service(update_user)
+            .service(delete_user)
             .app_data(Data::new(config.clone()))
+            // admins send users as raw bytes, so we can check their NIP-98 payload. They may
+            // carry an image, so allow as much as a json body would
+            .app_data(PayloadConfig::new(2 * 1024 * 1024))
     })
     .bind(host)?
     .run()
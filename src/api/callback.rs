@@ -2,22 +2,38 @@ use std::fmt::Debug;
 
 use actix_web::get;
 use actix_web::web;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use actix_web::Responder;
 
 use super::config::ServerConfig;
 use super::error::ApiError;
+use super::lnaddress::load_user;
+use super::payer_data::validate_payer_data;
+use super::success_action::SuccessActionResponse;
+use crate::backend::InvoiceDescription;
+use crate::backend::InvoiceRequest;
+use crate::db::Payment;
+use crate::db::PaymentStatus;
 use crate::nostr::nostr_event::Event;
+use crate::nostr::zap_handler::now;
 use crate::nostr::zap_handler::PendingZap;
-use crate::phoenixd::GetInvoiceResponse;
+use crate::nostr::zap_request::validate_zap_request;
+use crate::nostr::zap_request::ZapRequestError;
+use crate::users::SuccessAction;
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize)]
 /// The response for a the lnurlpay request. This is returned by the "/callback" endpoint
 pub struct LnUrlPayResponse {
     /// The actual invoice
     pr: String,
     /// We don't use this field, but it's required
     routes: Vec<String>,
+    /// What the wallet should show after paying (LUD-09)
+    #[serde(rename = "successAction", skip_serializing_if = "Option::is_none")]
+    success_action: Option<SuccessActionResponse>,
+    /// Where the payer can check whether this invoice was paid (LUD-21)
+    verify: String,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -27,48 +43,138 @@ pub struct LnUrlPayRequest {
     amount: u64,
     /// for zaps
     nostr: Option<String>,
+    /// A message for the payee (LUD-12)
+    comment: Option<String>,
+    /// A json identifying the payer (LUD-18)
+    payerdata: Option<String>,
 }
 
-#[get("/callback")]
+#[get("/callback/{user}")]
 pub async fn ln_url_callback(
+    user: web::Path<String>,
     amount: web::Query<LnUrlPayRequest>,
+    req: HttpRequest,
     client: web::Data<ServerConfig>,
 ) -> Result<impl Responder, ApiError> {
-    let LnUrlPayRequest { amount, nostr } = amount.into_inner();
-    let amount = amount / 1_000;
-    if amount == 0 {
+    let username = user.into_inner();
+    let user = load_user(client.users.as_ref(), &username)?;
+
+    let LnUrlPayRequest {
+        amount,
+        nostr,
+        comment,
+        payerdata,
+    } = amount.into_inner();
+    let (min_sendable, max_sendable) = client.sendable(&username, &user)?;
+    if amount < min_sendable || amount == 0 {
         return Err(ApiError::AmountTooSmall);
     }
 
-    let amount = format!("{}", amount);
-    let values = [("description", "zap"), ("amountSat", &amount)];
-
-    let res = client
-        .ph_client
-        .client
-        .post(format!("http://{}/createinvoice", client.ph_client.host))
-        .basic_auth("".to_string(), Some(&client.ph_client.password))
-        .form(&values)
-        .send()
-        .await?
-        .text()
+    if amount > max_sendable {
+        return Err(ApiError::AmountTooLarge);
+    }
+
+    // LUD-12 counts chars, not bytes
+    let comment = comment.filter(|comment| !comment.is_empty());
+    if let Some(comment) = &comment {
+        if comment.chars().count() > user.comment_allowed as usize {
+            return Err(ApiError::CommentTooLong);
+        }
+    }
+
+    let payer_data = payerdata.filter(|payer_data| !payer_data.is_empty());
+    validate_payer_data(
+        user.payer_data.as_ref(),
+        payer_data.as_deref(),
+        &client.secret_key,
+        &username,
+    )?;
+
+    // check the zap request before creating an invoice, we don't want to publish receipts for
+    // bogus requests
+    let zap_request = match nostr {
+        Some(raw) => {
+            // zaps are only allowed for users with a nostr key, and they should be credited to
+            // them
+            let receiver = user.nostr_pubkey().ok_or(ZapRequestError::ZapsNotAllowed)?;
+            let event: Event =
+                ::serde_json::from_str(&raw).map_err(|_| ZapRequestError::Malformed)?;
+            let sender = validate_zap_request(&event, &receiver, amount)?;
+
+            Some((raw, event, receiver, sender))
+        }
+        None => None,
+    };
+
+    // wallets check that the invoice commits to the metadata we gave them (LUD-06), followed by
+    // their payer data if they sent any (LUD-18). For zaps it should commit to the zap request
+    // instead (NIP-57)
+    let description = match &zap_request {
+        Some((raw, ..)) => raw.clone(),
+        None => user.metadata + payer_data.as_deref().unwrap_or_default(),
+    };
+
+    let response = client
+        .backend
+        .create_invoice(InvoiceRequest {
+            amount_msat: amount,
+            description: InvoiceDescription::Hashed(description),
+        })
         .await?;
 
-    let response: GetInvoiceResponse = serde_json::from_str(&res)?;
+    // record it before giving it out, so every invoice we hand out is in our ledger
+    let created_at = now();
+    client.db.insert_payment(&Payment {
+        username: username.clone(),
+        amount_msat: amount,
+        payment_hash: response.payment_hash.clone(),
+        bolt11: response.bolt11.clone(),
+        zap_request: zap_request.as_ref().map(|(raw, ..)| raw.clone()),
+        comment,
+        payer_data,
+        status: PaymentStatus::Pending,
+        created_at,
+        settled_at: None,
+        preimage: None,
+    })?;
+
+    let success_action = match &user.success_action {
+        Some(action) => {
+            // only aes needs the preimage, and some backends only give it when we look the
+            // payment up, so don't make every payment wait for that
+            let preimage = match (action, response.preimage) {
+                (SuccessAction::Aes { .. }, None) => {
+                    client
+                        .backend
+                        .lookup_incoming_payment(&response.payment_hash)
+                        .await?
+                        .preimage
+                }
+                (_, preimage) => preimage,
+            };
+
+            Some(SuccessActionResponse::new(action, preimage.as_deref())?)
+        }
+        None => None,
+    };
+
     let http_res = LnUrlPayResponse {
-        pr: response.serialized,
+        pr: response.bolt11,
         routes: vec![],
+        success_action,
+        verify: format!("{}/verify/{}", client.base_url(&req), response.payment_hash),
     };
-    if let Some(nostr) = nostr {
-        let nostr: Event = ::serde_json::from_str(&nostr)?;
+    if let Some((description, event, receiver, sender)) = zap_request {
         let zap = PendingZap {
+            lnurl: format!("{}/.well-known/lnurlp/{username}", client.base_url(&req)),
+            relays: PendingZap::relays_from_request(&event),
+            description,
             payment_hash: response.payment_hash,
             bolt11: http_res.pr.clone(),
-            receiver: "e0cfb5549d3cf7db4e2736f8e1bc84f62486af7a41295d867c6a313459042528"
-                .parse()
-                .unwrap(),
-            sender: nostr.pubkey.parse().unwrap(),
-            event: nostr,
+            receiver,
+            sender,
+            event,
+            created_at,
         };
 
         client.zap_sender.send(zap).await.expect("zap handler died");
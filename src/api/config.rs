@@ -1,7 +1,17 @@
+use std::sync::Arc;
+
+use actix_web::HttpRequest;
+use secp256k1::SecretKey;
+use secp256k1::XOnlyPublicKey;
 use tokio::sync::mpsc::Sender;
 
+use super::error::ApiError;
+use crate::backend::LightningBackend;
+use crate::db::Database;
+use crate::nostr::nip98::SeenEvents;
 use crate::nostr::zap_handler::PendingZap;
-use crate::phoenixd::PhoenixdClient;
+use crate::users::UserData;
+use crate::users::UserStore;
 
 #[derive(Clone)]
 /// General configuration and state for our server
@@ -9,14 +19,110 @@ use crate::phoenixd::PhoenixdClient;
 /// THis struct will be used as the app data inside the server, so we'll aways
 /// have access to it inside every endpoint
 pub struct ServerConfig {
-    /// A phoenixd client we use to talk with phoenixd
-    pub ph_client: PhoenixdClient,
+    /// The lightning wallet we use to create invoices
+    pub backend: Arc<dyn LightningBackend>,
     /// Where we can find our user's data
-    pub users_dir: String,
+    pub users: Arc<dyn UserStore>,
     /// The ip and port the API should listen to
     pub host: String,
     /// A sender to our zap handler
     pub zap_sender: Sender<PendingZap>,
     /// The pubkey of our lnaddress server. This is used to sign the zap receipt
     pub zap_pk: String,
+    /// The secret key for zap_pk. We also use it to derive the k1s for LUD-18 auth
+    pub secret_key: SecretKey,
+    /// The public url for this server, without a trailing slash. If we don't have one, we use
+    /// the address each request was sent to
+    pub base_url: Option<String>,
+    /// The domain our lightning addresses live in, like "smith.com" for "john@smith.com". If we
+    /// don't have one, we use the host each request was sent to
+    pub domain: Option<String>,
+    /// Where we keep our payment ledger
+    pub db: Database,
+    /// The relays we publish to. We also advertise them for our users' nostr keys
+    pub relays: Vec<String>,
+    /// The smallest amount users can receive by default, in milisats
+    pub min_sendable: u64,
+    /// The biggest amount users can receive by default, in milisats
+    pub max_sendable: u64,
+    /// The keys allowed to use the admin endpoints. If it's empty, they are disabled
+    pub admins: Vec<XOnlyPublicKey>,
+    /// The NIP-98 events admins used lately, so they can't be replayed
+    pub seen_auth_events: Arc<SeenEvents>,
+}
+
+impl ServerConfig {
+    /// Returns the public url for this server, like "https://smith.com"
+    pub fn base_url(&self, req: &HttpRequest) -> String {
+        if let Some(base_url) = &self.base_url {
+            return base_url.clone();
+        }
+
+        // this also considers the Forwarded and X-Forwarded-* headers set by proxies
+        let conn = req.connection_info();
+        format!("{}://{}", conn.scheme(), conn.host())
+    }
+
+    /// Returns the domain for our lightning addresses, like "smith.com"
+    ///
+    /// This may differ from [ServerConfig::base_url]'s host, e.g. if we're served from
+    /// "https://pay.smith.com/lnurl"
+    pub fn domain(&self, req: &HttpRequest) -> String {
+        match &self.domain {
+            Some(domain) => domain.clone(),
+            None => req.connection_info().host().to_owned(),
+        }
+    }
+
+    /// Returns the smallest and biggest amounts a user can receive, in milisats
+    ///
+    /// Users whose limits don't fit with ours can't receive anything, so, like other invalid
+    /// users, they are logged and treated as unknown
+    pub fn sendable(&self, username: &str, user: &UserData) -> Result<(u64, u64), ApiError> {
+        user.sendable(self.min_sendable, self.max_sendable)
+            .map_err(|e| {
+                println!("ignoring user {username}: {e}");
+                ApiError::UnknownUser
+            })
+    }
+}
+
+#[cfg(test)]
+impl ServerConfig {
+    /// A config for endpoint tests, serving `users` at "https://smith.com", with `admins` as our
+    /// admins. Our backend is unreachable, so endpoints can't create invoices
+    pub fn for_tests(users: Arc<dyn UserStore>, admins: Vec<XOnlyPublicKey>) -> Self {
+        use std::str::FromStr;
+
+        use secp256k1::Secp256k1;
+
+        use crate::backend::phoenixd::PhoenixdClient;
+
+        let secret_key =
+            SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000001")
+                .unwrap();
+        ServerConfig {
+            backend: Arc::new(PhoenixdClient {
+                client: reqwest::Client::default(),
+                password: "".into(),
+                host: "127.0.0.1:1".into(),
+            }),
+            users,
+            host: "127.0.0.1:8080".into(),
+            zap_sender: tokio::sync::mpsc::channel(1).0,
+            zap_pk: secret_key
+                .x_only_public_key(&Secp256k1::new())
+                .0
+                .to_string(),
+            secret_key,
+            base_url: Some("https://smith.com".into()),
+            domain: Some("smith.com".into()),
+            db: Database::open(":memory:").unwrap(),
+            relays: Vec::new(),
+            min_sendable: 1_000,
+            max_sendable: 10_000_000,
+            admins,
+            seen_auth_events: Arc::default(),
+        }
+    }
 }
@@ -5,11 +5,22 @@ use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use serde_json::json;
 
+use super::payer_data::PayerDataError;
+use crate::backend::BackendError;
+use crate::nostr::nip98::Nip98Error;
+use crate::nostr::zap_request::ZapRequestError;
+use crate::users::UserStoreError;
+
 #[derive(Debug, Clone)]
 /// The errors returned by this API
 pub enum ApiError {
     /// The requested amount in milisats is less than our minSendable
     AmountTooSmall,
+    /// The requested amount in milisats is more than our maxSendable
+    AmountTooLarge,
+    /// Our backend can't create an invoice for exactly the requested amount, usually because it
+    /// isn't a whole number of sats
+    AmountNotWholeSats,
     /// Something went wrong with our backend. Usually it's our connection with
     /// phoenixd that had problems
     BackendError,
@@ -18,10 +29,30 @@ pub enum ApiError {
     InvalidString,
     /// Requested an unknown user
     UnknownUser,
+    /// Asked about a payment we don't know about
+    UnknownPayment,
     /// User sent us a string that's not ascii-encoded
     NonAsciiString,
     /// Requested username is too long
     StringTooLong,
+    /// The zap request sent to us is invalid
+    InvalidZapRequest(ZapRequestError),
+    /// The comment sent with a payment is longer than this user's commentAllowed
+    CommentTooLong,
+    /// The payer data sent with a payment isn't what this user asked for
+    InvalidPayerData(PayerDataError),
+    /// Something went wrong with our database
+    DatabaseError,
+    /// This endpoint is only for admins, and the request isn't authenticated as one
+    Unauthorized,
+    /// The request has a NIP-98 auth event, but it isn't valid for this request
+    InvalidAuth(Nip98Error),
+    /// Tried to create a user that already exists
+    UserAlreadyExists,
+    /// The user an admin sent us isn't something we can serve
+    InvalidUser(String),
+    /// The image an admin sent us isn't a base64-encoded png or jpeg
+    InvalidImage,
 }
 
 impl Display for ApiError {
@@ -39,6 +70,12 @@ impl actix_web::ResponseError for ApiError {
             ApiError::AmountTooSmall => {
                 StatusCode::from_u16(400).expect("hardcoded value should be valid")
             }
+            ApiError::AmountTooLarge => {
+                StatusCode::from_u16(400).expect("hardcoded value should be valid")
+            }
+            ApiError::AmountNotWholeSats => {
+                StatusCode::from_u16(400).expect("hardcoded value should be valid")
+            }
             ApiError::InvalidString => {
                 StatusCode::from_u16(400).expect("hardcoded value should be valid")
             }
@@ -51,6 +88,36 @@ impl actix_web::ResponseError for ApiError {
             ApiError::UnknownUser => {
                 StatusCode::from_u16(404).expect("hardcoded value should be valid")
             }
+            ApiError::UnknownPayment => {
+                StatusCode::from_u16(404).expect("hardcoded value should be valid")
+            }
+            ApiError::InvalidZapRequest(_) => {
+                StatusCode::from_u16(400).expect("hardcoded value should be valid")
+            }
+            ApiError::CommentTooLong => {
+                StatusCode::from_u16(400).expect("hardcoded value should be valid")
+            }
+            ApiError::InvalidPayerData(_) => {
+                StatusCode::from_u16(400).expect("hardcoded value should be valid")
+            }
+            ApiError::DatabaseError => {
+                StatusCode::from_u16(500).expect("hardcoded value should be valid")
+            }
+            ApiError::Unauthorized => {
+                StatusCode::from_u16(401).expect("hardcoded value should be valid")
+            }
+            ApiError::InvalidAuth(_) => {
+                StatusCode::from_u16(401).expect("hardcoded value should be valid")
+            }
+            ApiError::UserAlreadyExists => {
+                StatusCode::from_u16(409).expect("hardcoded value should be valid")
+            }
+            ApiError::InvalidUser(_) => {
+                StatusCode::from_u16(400).expect("hardcoded value should be valid")
+            }
+            ApiError::InvalidImage => {
+                StatusCode::from_u16(400).expect("hardcoded value should be valid")
+            }
         }
     }
 
@@ -58,22 +125,71 @@ impl actix_web::ResponseError for ApiError {
         match self {
             ApiError::AmountTooSmall => HttpResponse::BadRequest()
                 .json(json!({"status": "ERROR", "reason": "amount too small"})),
+            ApiError::AmountTooLarge => HttpResponse::BadRequest()
+                .json(json!({"status": "ERROR", "reason": "amount too large"})),
+            ApiError::AmountNotWholeSats => HttpResponse::BadRequest().json(
+                json!({"status": "ERROR", "reason": "amount must be a whole number of sats"}),
+            ),
             ApiError::BackendError => HttpResponse::InternalServerError().into(),
             ApiError::UnknownUser => HttpResponse::NotFound()
                 .json(json!({"status": "ERROR", "reason": "user not found"})),
+            ApiError::UnknownPayment => HttpResponse::NotFound()
+                .json(json!({"status": "ERROR", "reason": "payment not found"})),
             ApiError::NonAsciiString => HttpResponse::BadRequest()
                 .json(json!({"status": "ERROR", "reason": "non-ascii char found in string"})),
             ApiError::StringTooLong => HttpResponse::BadRequest()
                 .json(json!({"status": "ERROR", "reason": "provided string is too long"})),
             ApiError::InvalidString => HttpResponse::BadRequest()
                 .json(json!({"status": "ERROR", "reason": "invalid char found in string"})),
+            ApiError::InvalidZapRequest(reason) => HttpResponse::BadRequest().json(
+                json!({"status": "ERROR", "reason": format!("invalid zap request: {reason}")}),
+            ),
+            ApiError::CommentTooLong => HttpResponse::BadRequest()
+                .json(json!({"status": "ERROR", "reason": "comment is too long"})),
+            ApiError::InvalidPayerData(reason) => HttpResponse::BadRequest().json(
+                json!({"status": "ERROR", "reason": format!("invalid payer data: {reason}")}),
+            ),
+            ApiError::DatabaseError => HttpResponse::InternalServerError().into(),
+            ApiError::Unauthorized => HttpResponse::Unauthorized()
+                .json(json!({"status": "ERROR", "reason": "unauthorized"})),
+            ApiError::InvalidAuth(reason) => HttpResponse::Unauthorized()
+                .json(json!({"status": "ERROR", "reason": format!("invalid auth: {reason}")})),
+            ApiError::UserAlreadyExists => HttpResponse::Conflict()
+                .json(json!({"status": "ERROR", "reason": "user already exists"})),
+            ApiError::InvalidUser(reason) => HttpResponse::BadRequest()
+                .json(json!({"status": "ERROR", "reason": format!("invalid user: {reason}")})),
+            ApiError::InvalidImage => HttpResponse::BadRequest().json(
+                json!({"status": "ERROR", "reason": "image must be a base64-encoded png or jpeg"}),
+            ),
         }
     }
 }
 
-impl From<reqwest::Error> for ApiError {
-    fn from(_value: reqwest::Error) -> Self {
-        ApiError::BackendError
+impl From<BackendError> for ApiError {
+    fn from(value: BackendError) -> Self {
+        println!("{value}");
+        match value {
+            BackendError::UnsupportedAmount(_) => ApiError::AmountNotWholeSats,
+            _ => ApiError::BackendError,
+        }
+    }
+}
+
+impl From<ZapRequestError> for ApiError {
+    fn from(value: ZapRequestError) -> Self {
+        ApiError::InvalidZapRequest(value)
+    }
+}
+
+impl From<PayerDataError> for ApiError {
+    fn from(value: PayerDataError) -> Self {
+        ApiError::InvalidPayerData(value)
+    }
+}
+
+impl From<Nip98Error> for ApiError {
+    fn from(value: Nip98Error) -> Self {
+        ApiError::InvalidAuth(value)
     }
 }
 
@@ -83,3 +199,17 @@ impl From<serde_json::Error> for ApiError {
         ApiError::BackendError
     }
 }
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(value: rusqlite::Error) -> Self {
+        println!("database error: {value}");
+        ApiError::DatabaseError
+    }
+}
+
+impl From<UserStoreError> for ApiError {
+    fn from(value: UserStoreError) -> Self {
+        println!("{value}");
+        ApiError::BackendError
+    }
+}
@@ -1,14 +1,17 @@
 use actix_web::get;
 use actix_web::web;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use actix_web::Responder;
-use serde::Deserialize;
 use serde::Serialize;
 
 use super::config::ServerConfig;
 use super::error::ApiError;
+use super::payer_data::PayerDataRequest;
+use crate::users::UserData;
+use crate::users::UserStore;
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize)]
 /// Data returned to the ".well-known/lnurlp/{username}" endpoint
 pub struct LnAddressInfo {
     #[serde(rename = "maxSendable")]
@@ -23,29 +26,26 @@ pub struct LnAddressInfo {
     callback: String,
     /// A stringfyed json with some metadata about ourselves
     metadata: String,
-    /// Do we support nostr?
+    /// Do we support nostr? Only true if this user has a nostr pubkey
     #[serde(rename = "allowsNostr")]
     allows_nostr: bool,
-    /// the server's public key
-    #[serde(rename = "nostrPubkey")]
-    nostr_pubkey: String,
+    /// the server's public key, used to sign zap receipts
+    #[serde(rename = "nostrPubkey", skip_serializing_if = "Option::is_none")]
+    nostr_pubkey: Option<String>,
+    /// How long the comments sent with a payment can be (LUD-12), if this user accepts them
+    #[serde(rename = "commentAllowed", skip_serializing_if = "Option::is_none")]
+    comment_allowed: Option<u32>,
+    /// Which information the payer should send about themselves (LUD-18)
+    #[serde(rename = "payerData", skip_serializing_if = "Option::is_none")]
+    payer_data: Option<PayerDataRequest>,
 }
 
-#[derive(Default, Serialize, Deserialize)]
-pub struct UserData {
-    /// The address that should be called to obtain an invoice
-    callback: String,
-    /// A stringfyed json with some metadata about ourselves
-    metadata: String,
-}
-
-#[get("/.well-known/lnurlp/{user}")]
-pub async fn well_known(
-    user: web::Path<String>,
-    app_data: web::Data<ServerConfig>,
-) -> Result<impl Responder, ApiError> {
-    let user = user.into_inner();
-
+/// Checks whether a username is something we can use to look for a user.
+///
+/// We do fs operations with user-provided data, so only short alphanumeric names are allowed.
+/// Underscores are fine too, so "_" can be used for the domain itself (as in NIP-05's
+/// "_@smith.com")
+pub fn validate_username(user: &str) -> Result<(), ApiError> {
     // don't allow non-ascii string
     if !user.is_ascii() {
         return Err(ApiError::NonAsciiString);
@@ -59,26 +59,103 @@ pub async fn well_known(
     // check for any non-alphanumeric chars
     let all_alph = user
         .chars()
-        .all(|ch| (ch >= 'a' && ch <= 'z') || (ch >= 'A' && ch <= 'A') || (ch >= '0' && ch <= '9'));
+        .all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
 
     if !all_alph {
         return Err(ApiError::InvalidString);
     }
 
-    let users_dir = &app_data.as_ref().users_dir;
-    let user = std::fs::read_to_string(format!("{users_dir}/{user}"))
-        .map(|user| serde_json::from_str::<UserData>(&user))
-        .map_err(|_| ApiError::UnknownUser)??;
+    Ok(())
+}
+
+/// Validates a username and loads its data from our user store
+pub fn load_user(users: &dyn UserStore, user: &str) -> Result<UserData, ApiError> {
+    validate_username(user)?;
+
+    users.get(user)?.ok_or(ApiError::UnknownUser)
+}
 
+#[get("/.well-known/lnurlp/{user}")]
+pub async fn well_known(
+    user: web::Path<String>,
+    req: HttpRequest,
+    app_data: web::Data<ServerConfig>,
+) -> Result<impl Responder, ApiError> {
+    let username = user.into_inner();
+    let user = load_user(app_data.users.as_ref(), &username)?;
+
+    let callback = format!("{}/callback/{username}", app_data.base_url(&req));
+
+    let (min_sendable, max_sendable) = app_data.sendable(&username, &user)?;
+    let allows_nostr = user.nostr_pubkey().is_some();
     let response = LnAddressInfo {
         tag: "payRequest".into(),
-        callback: user.callback,
+        callback,
         metadata: user.metadata,
-        max_sendable: 10_000_000,
-        min_sendable: 1,
-        nostr_pubkey: app_data.as_ref().zap_pk.clone(),
-        allows_nostr: true,
+        max_sendable,
+        min_sendable,
+        nostr_pubkey: allows_nostr.then(|| app_data.as_ref().zap_pk.clone()),
+        allows_nostr,
+        comment_allowed: (user.comment_allowed > 0).then_some(user.comment_allowed),
+        payer_data: user
+            .payer_data
+            .as_ref()
+            .map(|settings| PayerDataRequest::new(settings, &app_data.secret_key, &username)),
     };
 
     Ok(HttpResponse::Ok().json(response))
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::web::Data;
+    use actix_web::App;
+    use serde_json::Value;
+
+    use super::well_known;
+    use crate::api::config::ServerConfig;
+    use crate::db::Database;
+    use crate::users::sqlite::SqliteUserStore;
+    use crate::users::UserData;
+    use crate::users::UserStore;
+
+    #[actix_web::test]
+    async fn test_well_known() {
+        let users = Arc::new(SqliteUserStore::new(Database::open(":memory:").unwrap()));
+        let mut user = UserData {
+            metadata: r#"[["text/plain", "john"]]"#.into(),
+            max_sendable: Some(50_000_000),
+            ..Default::default()
+        };
+        users.put("john", &user).unwrap();
+
+        // fine on its own, but below our global minimum
+        user.max_sendable = Some(500_000);
+        users.put("mary", &user).unwrap();
+
+        let mut config = ServerConfig::for_tests(users, Vec::new());
+        config.min_sendable = 1_000_000;
+        let app =
+            test::init_service(App::new().service(well_known).app_data(Data::new(config))).await;
+
+        let req = test::TestRequest::get()
+            .uri("/.well-known/lnurlp/john")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let john: Value = test::read_body_json(res).await;
+        assert_eq!(john["minSendable"], 1_000_000);
+        assert_eq!(john["maxSendable"], 50_000_000);
+        assert_eq!(john["callback"], "https://smith.com/callback/john");
+
+        let req = test::TestRequest::get()
+            .uri("/.well-known/lnurlp/mary")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
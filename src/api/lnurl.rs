@@ -0,0 +1,76 @@
+use actix_web::get;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+
+use super::config::ServerConfig;
+use super::error::ApiError;
+use super::lnaddress::load_user;
+use crate::bech32;
+
+#[derive(Debug, Serialize)]
+/// Other ways to reach a user's pay endpoint, for wallets that don't know about lightning
+/// addresses. Both point to the same "/.well-known/lnurlp/{username}" url
+pub struct LnUrls {
+    /// The url, bech32-encoded with "lnurl" as prefix (LUD-01).
+    ///
+    /// It's uppercase, since that makes for smaller QR codes. Wallets take it in either case
+    lnurl: String,
+    /// The url with its scheme replaced by "lnurlp://" (LUD-17)
+    lnurlp: String,
+}
+
+impl LnUrls {
+    pub fn new(base_url: &str, username: &str) -> Self {
+        let url = format!("{base_url}/.well-known/lnurlp/{username}");
+
+        // LUD-17 wallets go back to https, or http for onion hosts
+        let without_scheme = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+
+        LnUrls {
+            lnurl: bech32::encode("lnurl", url.as_bytes()).to_uppercase(),
+            lnurlp: format!("lnurlp://{without_scheme}"),
+        }
+    }
+}
+
+#[get("/lnurl/{user}")]
+pub async fn lnurl(
+    user: web::Path<String>,
+    req: HttpRequest,
+    app_data: web::Data<ServerConfig>,
+) -> Result<impl Responder, ApiError> {
+    let username = user.into_inner();
+    load_user(app_data.users.as_ref(), &username)?;
+
+    Ok(HttpResponse::Ok().json(LnUrls::new(&app_data.base_url(&req), &username)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::LnUrls;
+    use crate::bech32;
+
+    #[test]
+    fn test_lnurls() {
+        let urls = LnUrls::new("https://smith.com", "john");
+        assert_eq!(urls.lnurlp, "lnurlp://smith.com/.well-known/lnurlp/john");
+
+        assert!(urls.lnurl.starts_with("LNURL1"));
+        assert_eq!(urls.lnurl, urls.lnurl.to_uppercase());
+        let (hrp, data) = bech32::decode(&urls.lnurl).unwrap();
+        assert_eq!(hrp, "lnurl");
+        assert_eq!(data, b"https://smith.com/.well-known/lnurlp/john");
+
+        // wallets that only take lowercase get the same url
+        let (_, data) = bech32::decode(&urls.lnurl.to_lowercase()).unwrap();
+        assert_eq!(data, b"https://smith.com/.well-known/lnurlp/john");
+
+        let urls = LnUrls::new("http://127.0.0.1:8080", "john");
+        assert_eq!(
+            urls.lnurlp,
+            "lnurlp://127.0.0.1:8080/.well-known/lnurlp/john"
+        );
+    }
+}
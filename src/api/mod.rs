@@ -1,5 +1,12 @@
+mod admin;
+#[allow(clippy::module_inception)]
 pub mod api;
 pub mod callback;
 pub mod config;
 mod error;
-mod lnaddress;
+pub mod lnaddress;
+mod lnurl;
+mod nip05;
+mod payer_data;
+mod success_action;
+mod verify;
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use actix_web::get;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+
+use super::config::ServerConfig;
+use super::error::ApiError;
+use super::lnaddress::load_user;
+
+#[derive(Deserialize)]
+/// The query for "/.well-known/nostr.json"
+pub struct Nip05Request {
+    /// The name before the "@", like "john" for "john@smith.com"
+    name: Option<String>,
+}
+
+#[derive(Default, Serialize)]
+/// Maps names to nostr keys, and keys to the relays where they can be found (NIP-05)
+pub struct Nip05Response {
+    /// Hex-encoded keys for each name
+    names: HashMap<String, String>,
+    /// The relays for each key
+    relays: HashMap<String, Vec<String>>,
+}
+
+#[get("/.well-known/nostr.json")]
+pub async fn nostr_json(
+    query: web::Query<Nip05Request>,
+    app_data: web::Data<ServerConfig>,
+) -> Result<impl Responder, ApiError> {
+    let mut response = Nip05Response::default();
+
+    // we don't give out every user, so only named queries get an answer
+    let Some(name) = query.into_inner().name else {
+        return Ok(HttpResponse::Ok().json(response));
+    };
+
+    // NIP-05 clients expect an empty answer for names we don't know
+    let user = match load_user(app_data.users.as_ref(), &name) {
+        Ok(user) => user,
+        Err(
+            ApiError::UnknownUser
+            | ApiError::InvalidString
+            | ApiError::NonAsciiString
+            | ApiError::StringTooLong,
+        ) => return Ok(HttpResponse::Ok().json(response)),
+        Err(e) => return Err(e),
+    };
+    if let Some(pubkey) = user.nostr_pubkey() {
+        let pubkey = pubkey.to_string();
+        response.names.insert(name, pubkey.clone());
+        response.relays.insert(pubkey, app_data.relays.clone());
+    }
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::web::Data;
+    use actix_web::App;
+    use serde_json::json;
+    use serde_json::Value;
+
+    use super::nostr_json;
+    use crate::api::config::ServerConfig;
+    use crate::db::Database;
+    use crate::users::sqlite::SqliteUserStore;
+    use crate::users::UserData;
+    use crate::users::UserStore;
+
+    const JOHN: &str = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+
+    #[actix_web::test]
+    async fn test_nostr_json() {
+        let users = Arc::new(SqliteUserStore::new(Database::open(":memory:").unwrap()));
+        let mut user = UserData {
+            metadata: r#"[["text/plain", "john"]]"#.into(),
+            nostr_pubkey: Some(
+                "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg".into(),
+            ),
+            ..Default::default()
+        };
+        users.put("john", &user).unwrap();
+        users.put("_", &user).unwrap();
+        user.nostr_pubkey = None;
+        users.put("mary", &user).unwrap();
+
+        let mut config = ServerConfig::for_tests(users, Vec::new());
+        config.relays = vec!["wss://relay.smith.com".into()];
+        let app =
+            test::init_service(App::new().service(nostr_json).app_data(Data::new(config))).await;
+
+        let query = |uri: &'static str| {
+            let app = &app;
+            async move {
+                let req = test::TestRequest::get().uri(uri).to_request();
+                let res = test::call_service(app, req).await;
+                assert_eq!(res.status(), StatusCode::OK);
+                test::read_body_json::<Value, _>(res).await
+            }
+        };
+
+        let john = json!({
+            "names": {"john": JOHN},
+            "relays": {JOHN: ["wss://relay.smith.com"]},
+        });
+        assert_eq!(query("/.well-known/nostr.json?name=john").await, john);
+        assert_eq!(
+            query("/.well-known/nostr.json?name=_").await["names"],
+            json!({"_": JOHN})
+        );
+
+        // names we can't answer for get an empty answer, not an error
+        let empty = json!({"names": {}, "relays": {}});
+        assert_eq!(query("/.well-known/nostr.json?name=bob").await, empty);
+        assert_eq!(query("/.well-known/nostr.json?name=mary").await, empty);
+        assert_eq!(query("/.well-known/nostr.json?name=john%21").await, empty);
+        assert_eq!(query("/.well-known/nostr.json?name=%C3%A9").await, empty);
+        assert_eq!(query("/.well-known/nostr.json").await, empty);
+    }
+}
@@ -0,0 +1,328 @@
+//! Payer identity data (LUD-18)
+//!
+//! Users may ask payers to identify themselves. We tell wallets which fields we want in the
+//! payRequest response, and they send them back as a json in the callback's `payerdata`
+//! parameter. The invoice then commits to both our metadata and this json.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use hex_conservative::DisplayHex;
+use hex_conservative::FromHex;
+use secp256k1::ecdsa::Signature;
+use secp256k1::Message;
+use secp256k1::PublicKey;
+use secp256k1::Secp256k1;
+use secp256k1::SecretKey;
+use secp256k1::XOnlyPublicKey;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::nostr::zap_handler::now;
+use crate::users::PayerDataField;
+use crate::users::PayerDataSettings;
+
+/// For how long (in seconds) the k1 we give to wallets for the auth field stays the same
+///
+/// k1s aren't stored anywhere, we derive them from our key, the user and the current period.
+/// We accept the current and the previous one, so wallets have at least this long to use them.
+const K1_PERIOD: u64 = 3600;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Why the payer data sent to us was rejected
+pub enum PayerDataError {
+    /// This user doesn't ask for payer data
+    NotAccepted,
+    /// The payer data isn't a valid json object
+    Malformed,
+    /// The payer data has a field this user didn't ask for
+    NotRequested(&'static str),
+    /// The payer data lacks a mandatory field
+    Missing(&'static str),
+    /// The pubkey field isn't a valid hex-encoded public key
+    InvalidPubkey,
+    /// The email field isn't an email
+    InvalidEmail,
+    /// The auth field doesn't sign one of our k1s
+    InvalidAuth,
+}
+
+impl Display for PayerDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayerDataError::NotAccepted => write!(f, "this user doesn't accept payer data"),
+            PayerDataError::Malformed => write!(f, "payer data should be a json object"),
+            PayerDataError::NotRequested(field) => write!(f, "{field} wasn't requested"),
+            PayerDataError::Missing(field) => write!(f, "{field} is mandatory"),
+            PayerDataError::InvalidPubkey => write!(f, "invalid pubkey"),
+            PayerDataError::InvalidEmail => write!(f, "invalid email"),
+            PayerDataError::InvalidAuth => write!(f, "invalid auth signature"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+/// The auth field we send to wallets. Unlike the others, it has a k1 they should sign
+pub struct AuthRequest {
+    mandatory: bool,
+    k1: String,
+}
+
+#[derive(Serialize)]
+/// The payerData we send to wallets, telling which fields we want
+pub struct PayerDataRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<PayerDataField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pubkey: Option<PayerDataField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identifier: Option<PayerDataField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<PayerDataField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<AuthRequest>,
+}
+
+impl PayerDataRequest {
+    pub fn new(settings: &PayerDataSettings, secret_key: &SecretKey, username: &str) -> Self {
+        Self {
+            name: settings.name,
+            pubkey: settings.pubkey,
+            identifier: settings.identifier,
+            email: settings.email,
+            auth: settings.auth.map(|auth| AuthRequest {
+                mandatory: auth.mandatory,
+                k1: auth_k1(secret_key, username, now() / K1_PERIOD),
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+/// The auth field, as sent by wallets. This works like a LUD-04 login
+struct PayerAuth {
+    /// The payer's linking key, hex-encoded
+    key: String,
+    /// The k1 we gave them
+    k1: String,
+    /// A DER-encoded signature of k1 with key
+    sig: String,
+}
+
+#[derive(Default, Deserialize)]
+/// The payer data, as sent by wallets
+struct PayerData {
+    name: Option<String>,
+    pubkey: Option<String>,
+    identifier: Option<String>,
+    email: Option<String>,
+    auth: Option<PayerAuth>,
+}
+
+/// Returns the k1 wallets should sign for a user during a given period
+fn auth_k1(secret_key: &SecretKey, username: &str, period: u64) -> String {
+    let mut engine = Sha256::new();
+    engine.update(b"lud18-auth");
+    engine.update(secret_key.secret_bytes());
+    engine.update(username.as_bytes());
+    engine.update(period.to_be_bytes());
+
+    engine.finalize().to_lower_hex_string()
+}
+
+/// Checks whether a field was sent if mandatory, and only sent if requested
+fn check_field<T>(
+    name: &'static str,
+    field: Option<PayerDataField>,
+    value: &Option<T>,
+) -> Result<(), PayerDataError> {
+    match (field, value) {
+        (None, Some(_)) => Err(PayerDataError::NotRequested(name)),
+        (Some(field), None) if field.mandatory => Err(PayerDataError::Missing(name)),
+        _ => Ok(()),
+    }
+}
+
+/// Checks whether an auth field signs one of the k1s we gave out for this user
+fn check_auth(auth: &PayerAuth, secret_key: &SecretKey, username: &str) -> bool {
+    let period = now() / K1_PERIOD;
+    let ours = [period, period.saturating_sub(1)]
+        .into_iter()
+        .any(|period| auth_k1(secret_key, username, period) == auth.k1);
+
+    let (Ok(k1), Ok(key), Ok(sig)) = (
+        <[u8; 32]>::from_hex(&auth.k1),
+        PublicKey::from_str(&auth.key),
+        Vec::<u8>::from_hex(&auth.sig).map(|sig| Signature::from_der(&sig)),
+    ) else {
+        return false;
+    };
+
+    let Ok(sig) = sig else {
+        return false;
+    };
+
+    ours && Secp256k1::verification_only()
+        .verify_ecdsa(&Message::from_digest(k1), &sig, &key)
+        .is_ok()
+}
+
+/// Checks whether `raw` is payer data we can accept for this user. Payments without payer data
+/// are only accepted if this user has no mandatory fields
+pub fn validate_payer_data(
+    settings: Option<&PayerDataSettings>,
+    raw: Option<&str>,
+    secret_key: &SecretKey,
+    username: &str,
+) -> Result<(), PayerDataError> {
+    let data = match (settings, raw) {
+        (_, Some(raw)) => serde_json::from_str(raw).map_err(|_| PayerDataError::Malformed)?,
+        (Some(_), None) => PayerData::default(),
+        (None, None) => return Ok(()),
+    };
+    let settings = settings.ok_or(PayerDataError::NotAccepted)?;
+
+    check_field("name", settings.name, &data.name)?;
+    check_field("pubkey", settings.pubkey, &data.pubkey)?;
+    check_field("identifier", settings.identifier, &data.identifier)?;
+    check_field("email", settings.email, &data.email)?;
+    check_field("auth", settings.auth, &data.auth)?;
+
+    if let Some(pubkey) = &data.pubkey {
+        if PublicKey::from_str(pubkey).is_err() && XOnlyPublicKey::from_str(pubkey).is_err() {
+            return Err(PayerDataError::InvalidPubkey);
+        }
+    }
+
+    if let Some(email) = &data.email {
+        let valid = email
+            .split_once('@')
+            .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.'));
+
+        if !valid || email.contains(char::is_whitespace) {
+            return Err(PayerDataError::InvalidEmail);
+        }
+    }
+
+    if let Some(auth) = &data.auth {
+        if !check_auth(auth, secret_key, username) {
+            return Err(PayerDataError::InvalidAuth);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use hex_conservative::DisplayHex;
+    use hex_conservative::FromHex;
+    use secp256k1::Message;
+    use secp256k1::Secp256k1;
+    use secp256k1::SecretKey;
+    use serde_json::json;
+
+    use super::auth_k1;
+    use super::now;
+    use super::validate_payer_data;
+    use super::PayerDataError;
+    use super::K1_PERIOD;
+    use crate::users::PayerDataField;
+    use crate::users::PayerDataSettings;
+
+    fn secret_key() -> SecretKey {
+        SecretKey::from_str("d7bee682d987439fae91bdc5fed8bbf16d84ec077a2bd5cf7592e384668198f3")
+            .unwrap()
+    }
+
+    fn auth(k1: &str) -> serde_json::Value {
+        let linking_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let secp = Secp256k1::new();
+        let msg = Message::from_digest(<[u8; 32]>::from_hex(k1).unwrap());
+        let sig = secp.sign_ecdsa(&msg, &linking_key);
+
+        json!({
+            "key": linking_key.public_key(&secp).to_string(),
+            "k1": k1,
+            "sig": sig.serialize_der().to_lower_hex_string(),
+        })
+    }
+
+    #[test]
+    fn test_validate_payer_data() {
+        let settings = PayerDataSettings {
+            name: Some(PayerDataField { mandatory: true }),
+            email: Some(PayerDataField { mandatory: false }),
+            auth: Some(PayerDataField { mandatory: false }),
+            ..Default::default()
+        };
+        let validate = |raw: serde_json::Value| {
+            validate_payer_data(
+                Some(&settings),
+                Some(&raw.to_string()),
+                &secret_key(),
+                "john",
+            )
+        };
+
+        assert_eq!(validate(json!({"name": "mary"})), Ok(()));
+        assert_eq!(
+            validate(json!({"name": "mary", "email": "mary@smith.com"})),
+            Ok(())
+        );
+        assert_eq!(
+            validate(json!({"email": "mary@smith.com"})),
+            Err(PayerDataError::Missing("name"))
+        );
+        assert_eq!(
+            validate(json!({"name": "mary", "pubkey": "02aa"})),
+            Err(PayerDataError::NotRequested("pubkey"))
+        );
+        assert_eq!(
+            validate(json!({"name": "mary", "email": "mary"})),
+            Err(PayerDataError::InvalidEmail)
+        );
+        assert_eq!(
+            validate_payer_data(None, Some("{}"), &secret_key(), "john"),
+            Err(PayerDataError::NotAccepted)
+        );
+        assert_eq!(
+            validate_payer_data(Some(&settings), Some("[]"), &secret_key(), "john"),
+            Err(PayerDataError::Malformed)
+        );
+        assert_eq!(
+            validate_payer_data(Some(&settings), None, &secret_key(), "john"),
+            Err(PayerDataError::Missing("name"))
+        );
+        assert_eq!(
+            validate_payer_data(None, None, &secret_key(), "john"),
+            Ok(())
+        );
+
+        // the k1 must be one we gave out, for this user, and recently
+        let k1 = auth_k1(&secret_key(), "john", now() / K1_PERIOD);
+        assert_eq!(validate(json!({"name": "mary", "auth": auth(&k1)})), Ok(()));
+
+        let k1 = auth_k1(&secret_key(), "mary", now() / K1_PERIOD);
+        assert_eq!(
+            validate(json!({"name": "mary", "auth": auth(&k1)})),
+            Err(PayerDataError::InvalidAuth)
+        );
+
+        let k1 = auth_k1(&secret_key(), "john", now() / K1_PERIOD - 2);
+        assert_eq!(
+            validate(json!({"name": "mary", "auth": auth(&k1)})),
+            Err(PayerDataError::InvalidAuth)
+        );
+
+        let k1 = auth_k1(&secret_key(), "john", now() / K1_PERIOD);
+        let mut forged = auth(&k1);
+        forged["k1"] = json!(auth_k1(&secret_key(), "john", now() / K1_PERIOD - 1));
+        assert_eq!(
+            validate(json!({"name": "mary", "auth": forged})),
+            Err(PayerDataError::InvalidAuth)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Success actions, shown by wallets after paying (LUD-09 and LUD-10)
+
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::BlockEncryptMut;
+use aes::cipher::KeyIvInit;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use hex_conservative::FromHex;
+
+use crate::backend::BackendError;
+use crate::users::SuccessAction;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "tag", rename_all = "lowercase")]
+/// A success action, as sent to wallets
+pub enum SuccessActionResponse {
+    Message {
+        message: String,
+    },
+    Url {
+        description: String,
+        url: String,
+    },
+    Aes {
+        description: String,
+        /// The secret, encrypted with AES-256-CBC, base64-encoded
+        ciphertext: String,
+        /// The IV for the ciphertext, base64-encoded
+        iv: String,
+    },
+}
+
+impl SuccessActionResponse {
+    /// Builds the success action for an invoice with the given hex-encoded preimage. Only aes
+    /// actions need it
+    pub fn new(action: &SuccessAction, preimage: Option<&str>) -> Result<Self, BackendError> {
+        Ok(match action {
+            SuccessAction::Message { message } => SuccessActionResponse::Message {
+                message: message.clone(),
+            },
+            SuccessAction::Url { description, url } => SuccessActionResponse::Url {
+                description: description.clone(),
+                url: url.clone(),
+            },
+            SuccessAction::Aes {
+                description,
+                secret,
+            } => {
+                let preimage =
+                    preimage.ok_or(BackendError::InvalidResponse("missing preimage".into()))?;
+                let key = <[u8; 32]>::from_hex(preimage)
+                    .map_err(|_| BackendError::InvalidResponse("invalid preimage".into()))?;
+                let iv: [u8; 16] = rand::random();
+                let ciphertext = encrypt(&key, &iv, secret.as_bytes());
+
+                SuccessActionResponse::Aes {
+                    description: description.clone(),
+                    ciphertext: BASE64_STANDARD.encode(ciphertext),
+                    iv: BASE64_STANDARD.encode(iv),
+                }
+            }
+        })
+    }
+}
+
+/// Encrypts `plaintext` with AES-256-CBC and PKCS7 padding, as LUD-10 wants
+fn encrypt(key: &[u8; 32], iv: &[u8; 16], plaintext: &[u8]) -> Vec<u8> {
+    Aes256CbcEnc::new(key.into(), iv.into()).encrypt_padded_vec_mut::<Pkcs7>(plaintext)
+}
+
+#[cfg(test)]
+mod test {
+    use aes::cipher::block_padding::Pkcs7;
+    use aes::cipher::BlockDecryptMut;
+    use aes::cipher::KeyIvInit;
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine;
+    use hex_conservative::DisplayHex;
+    use hex_conservative::FromHex;
+
+    use super::encrypt;
+    use super::SuccessActionResponse;
+    use crate::users::SuccessAction;
+
+    type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+    #[test]
+    fn test_encrypt() {
+        // NIST SP 800-38A, F.2.5 (CBC-AES256.Encrypt), first block. The second block is the
+        // PKCS7 padding
+        let key = <[u8; 32]>::from_hex(
+            "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4",
+        )
+        .unwrap();
+        let iv: [u8; 16] = std::array::from_fn(|i| i as u8);
+        let plaintext = <[u8; 16]>::from_hex("6bc1bee22e409f96e93d7e117393172a").unwrap();
+
+        let ciphertext = encrypt(&key, &iv, &plaintext);
+        assert_eq!(ciphertext.len(), 32);
+        assert_eq!(
+            ciphertext[..16].to_lower_hex_string(),
+            "f58c4c04d6e5f1ba779eabfb5f7bfbd6"
+        );
+    }
+
+    #[test]
+    fn test_aes_success_action() {
+        let preimage = [7u8; 32];
+        let action = SuccessAction::Aes {
+            description: "your code".into(),
+            secret: "1234-5678".into(),
+        };
+
+        let preimage_hex = preimage.to_lower_hex_string();
+        let response = SuccessActionResponse::new(&action, Some(&preimage_hex)).unwrap();
+        let SuccessActionResponse::Aes { ciphertext, iv, .. } = response else {
+            panic!("expected an aes success action");
+        };
+
+        let iv: [u8; 16] = BASE64_STANDARD.decode(iv).unwrap().try_into().unwrap();
+        let mut ciphertext = BASE64_STANDARD.decode(ciphertext).unwrap();
+        let secret = Aes256CbcDec::new(&preimage.into(), &iv.into())
+            .decrypt_padded_mut::<Pkcs7>(&mut ciphertext)
+            .unwrap();
+        assert_eq!(secret, b"1234-5678");
+
+        // we can't encrypt without the preimage, but other actions don't need it
+        assert!(SuccessActionResponse::new(&action, None).is_err());
+        let message = SuccessAction::Message {
+            message: "thanks!".into(),
+        };
+        assert!(SuccessActionResponse::new(&message, None).is_ok());
+    }
+}
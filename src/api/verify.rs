@@ -0,0 +1,68 @@
+use actix_web::get;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+
+use super::config::ServerConfig;
+use super::error::ApiError;
+use crate::db::PaymentStatus;
+
+#[derive(Serialize)]
+/// Whether an invoice was paid (LUD-21)
+pub struct VerifyResponse {
+    status: &'static str,
+    /// Whether this invoice was paid
+    settled: bool,
+    /// The invoice's preimage, hex-encoded. Only revealed after it's paid
+    preimage: Option<String>,
+    /// The invoice itself
+    pr: String,
+}
+
+#[get("/verify/{payment_hash}")]
+pub async fn verify(
+    payment_hash: web::Path<String>,
+    config: web::Data<ServerConfig>,
+) -> Result<impl Responder, ApiError> {
+    let payment_hash = payment_hash.into_inner();
+
+    // only answer for invoices we gave out, not anything our node knows about
+    let is_hash = payment_hash.len() == 64 && payment_hash.chars().all(|c| c.is_ascii_hexdigit());
+    if !is_hash {
+        return Err(ApiError::UnknownPayment);
+    }
+
+    let payment = config
+        .db
+        .get_payment(&payment_hash.to_lowercase())?
+        .ok_or(ApiError::UnknownPayment)?;
+
+    // anyone can call this, so answer from our ledger whenever we can instead of asking our
+    // backend every time
+    let (settled, preimage) = match (payment.status, payment.preimage) {
+        (PaymentStatus::Expired, _) => (false, None),
+        (PaymentStatus::Settled, Some(preimage)) => (true, Some(preimage)),
+        (status, _) => {
+            let incoming = config
+                .backend
+                .lookup_incoming_payment(&payment.payment_hash)
+                .await?;
+            let preimage = incoming.preimage.filter(|_| incoming.is_paid);
+
+            // some backends don't tell us the preimage when a payment arrives, keep it for next
+            // time
+            if let (PaymentStatus::Settled, Some(preimage)) = (status, &preimage) {
+                config.db.save_preimage(&payment.payment_hash, preimage)?;
+            }
+
+            (incoming.is_paid, preimage)
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(VerifyResponse {
+        status: "OK",
+        settled,
+        preimage,
+        pr: payment.bolt11,
+    }))
+}
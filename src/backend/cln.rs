@@ -0,0 +1,372 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use hex_conservative::DisplayHex;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use serde_json::Value;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::Receiver;
+
+use super::new_preimage;
+use super::BackendError;
+use super::IncomingPayment;
+use super::Invoice;
+use super::InvoiceDescription;
+use super::InvoiceRequest;
+use super::LightningBackend;
+use super::INVOICE_EXPIRY;
+
+/// A client for Core Lightning's JSON-RPC, talking through its `lightning-rpc` unix socket
+pub struct ClnClient {
+    /// The path for CLN's rpc socket, usually "~/.lightning/bitcoin/lightning-rpc"
+    rpc_path: String,
+    /// CLN requires invoice labels to be unique, we use this to build them
+    labels: AtomicU64,
+}
+
+#[derive(Deserialize)]
+/// Data returned from CLN when we call "invoice"
+struct InvoiceResponse {
+    /// The payment hash for this invoice, hex-encoded
+    payment_hash: String,
+    /// The actual bolt11 invoice
+    bolt11: String,
+}
+
+#[derive(Deserialize)]
+/// Data returned from CLN when we call "listinvoices"
+struct ListInvoicesResponse {
+    invoices: Vec<ClnInvoice>,
+}
+
+#[derive(Deserialize)]
+/// Data returned from CLN when we call "wait" for the invoices' "updated" index
+struct WaitResponse {
+    /// The current value of the index, bumped every time an invoice changes
+    updated: Option<u64>,
+}
+
+#[derive(Deserialize)]
+/// An invoice, as returned by "listinvoices"
+struct ClnInvoice {
+    /// The payment hash for this invoice, hex-encoded
+    payment_hash: String,
+    /// One of "unpaid", "paid" or "expired"
+    status: String,
+    /// How much we've received for this invoice, only present if it's paid
+    amount_received_msat: Option<u64>,
+    /// The preimage for this invoice, only present if it's paid
+    payment_preimage: Option<String>,
+    /// Where this invoice's last change is in the "updated" index
+    updated_index: Option<u64>,
+}
+
+impl From<ClnInvoice> for IncomingPayment {
+    fn from(invoice: ClnInvoice) -> Self {
+        IncomingPayment {
+            payment_hash: invoice.payment_hash,
+            is_paid: invoice.status == "paid",
+            received_msat: invoice.amount_received_msat.unwrap_or(0),
+            preimage: invoice.payment_preimage,
+        }
+    }
+}
+
+impl ClnClient {
+    pub fn new(rpc_path: String) -> Self {
+        Self {
+            rpc_path,
+            labels: AtomicU64::new(0),
+        }
+    }
+
+    /// Sends one request to CLN and waits for its response
+    ///
+    /// We open a new connection for every call, so long-running calls like "wait"
+    /// don't block everything else.
+    async fn call<T: DeserializeOwned>(
+        rpc_path: &str,
+        method: &str,
+        params: Value,
+    ) -> Result<T, BackendError> {
+        let mut socket = UnixStream::connect(rpc_path)
+            .await
+            .map_err(|e| BackendError::Connection(e.to_string()))?;
+
+        let request = json!({"jsonrpc": "2.0", "id": 0, "method": method, "params": params});
+        socket
+            .write_all(request.to_string().as_bytes())
+            .await
+            .map_err(|e| BackendError::Connection(e.to_string()))?;
+
+        // CLN doesn't close the connection after answering, so we read until we have a full
+        // json object
+        let mut buffer = Vec::new();
+        let response = loop {
+            let mut chunk = [0; 4096];
+            let read = socket
+                .read(&mut chunk)
+                .await
+                .map_err(|e| BackendError::Connection(e.to_string()))?;
+            if read == 0 {
+                return Err(BackendError::Connection("CLN closed the connection".into()));
+            }
+
+            buffer.extend_from_slice(&chunk[..read]);
+            match serde_json::from_slice::<Value>(&buffer) {
+                Ok(response) => break response,
+                Err(e) if e.is_eof() => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        if let Some(error) = response.get("error") {
+            return Err(BackendError::InvalidResponse(error.to_string()));
+        }
+
+        let result = response
+            .get("result")
+            .cloned()
+            .ok_or(BackendError::InvalidResponse("missing result".into()))?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Creates a label no other invoice created by us will have
+    fn new_label(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let count = self.labels.fetch_add(1, Ordering::Relaxed);
+        format!("ln-address-{now}-{count}")
+    }
+}
+
+#[async_trait]
+impl LightningBackend for ClnClient {
+    async fn create_invoice(&self, request: InvoiceRequest) -> Result<Invoice, BackendError> {
+        // CLN hashes the description itself, and only stores the hash if asked to
+        let (description, deschashonly) = match request.description {
+            InvoiceDescription::Text(text) => (text, false),
+            InvoiceDescription::Hashed(text) => (text, true),
+        };
+        let preimage = new_preimage().to_lower_hex_string();
+        let params = json!({
+            "preimage": preimage,
+            "amount_msat": request.amount_msat,
+            "label": self.new_label(),
+            "description": description,
+            "deschashonly": deschashonly,
+            "expiry": INVOICE_EXPIRY,
+        });
+
+        let response: InvoiceResponse = Self::call(&self.rpc_path, "invoice", params).await?;
+        Ok(Invoice {
+            payment_hash: response.payment_hash,
+            bolt11: response.bolt11,
+            preimage: Some(preimage),
+        })
+    }
+
+    async fn lookup_incoming_payment(
+        &self,
+        payment_hash: &str,
+    ) -> Result<IncomingPayment, BackendError> {
+        let params = json!({ "payment_hash": payment_hash });
+        let response: ListInvoicesResponse =
+            Self::call(&self.rpc_path, "listinvoices", params).await?;
+
+        response
+            .invoices
+            .into_iter()
+            .next()
+            .map(Into::into)
+            .ok_or(BackendError::InvalidResponse("unknown invoice".into()))
+    }
+
+    async fn subscribe_payments(&self) -> Result<Receiver<IncomingPayment>, BackendError> {
+        // CLN bumps the invoices' "updated" index every time one changes (e.g. gets paid), so we
+        // wait for it to move and only list the invoices changed since, instead of going through
+        // every invoice this node ever issued. Waiting for 0 returns its current value right away
+        let params = json!({"subsystem": "invoices", "indexname": "updated", "nextvalue": 0});
+        let current: WaitResponse = Self::call(&self.rpc_path, "wait", params).await?;
+        let mut next_index = current.updated.unwrap_or(0) + 1;
+
+        let (sender, receiver) = channel(1024);
+        let rpc_path = self.rpc_path.clone();
+        tokio::task::spawn(async move {
+            loop {
+                let params = json!({
+                    "subsystem": "invoices",
+                    "indexname": "updated",
+                    "nextvalue": next_index,
+                });
+                let updated = match Self::call::<WaitResponse>(&rpc_path, "wait", params).await {
+                    Ok(response) => response.updated.unwrap_or(next_index),
+                    Err(e) => {
+                        println!("{e}");
+                        return;
+                    }
+                };
+
+                let params = json!({"index": "updated", "start": next_index});
+                let response: ListInvoicesResponse =
+                    match Self::call(&rpc_path, "listinvoices", params).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            println!("{e}");
+                            return;
+                        }
+                    };
+
+                // deleted invoices bump the index too, but aren't listed anymore
+                next_index = next_index.max(updated + 1);
+                for invoice in response.invoices {
+                    next_index = next_index.max(invoice.updated_index.unwrap_or(0) + 1);
+                    if invoice.status != "paid" {
+                        continue;
+                    }
+
+                    if sender.send(invoice.into()).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use serde_json::Value;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::UnixListener;
+
+    use super::ClnClient;
+    use crate::backend::InvoiceDescription;
+    use crate::backend::InvoiceRequest;
+    use crate::backend::LightningBackend;
+
+    /// Starts a fake CLN that answers every request with `handler(method, params)`, and returns
+    /// the path for its socket
+    fn fake_cln(name: &str, handler: fn(&str, &Value) -> Value) -> String {
+        let path = std::env::temp_dir().join(format!("ln-address-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        tokio::task::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::task::spawn(async move {
+                    let mut buffer = vec![0; 4096];
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    let request: Value = serde_json::from_slice(&buffer[..read]).unwrap();
+
+                    let result = handler(request["method"].as_str().unwrap(), &request["params"]);
+                    let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+                        .to_string();
+
+                    // split the response so we exercise partial reads
+                    let (first, second) = response.split_at(response.len() / 2);
+                    socket.write_all(first.as_bytes()).await.unwrap();
+                    socket.flush().await.unwrap();
+                    socket.write_all(second.as_bytes()).await.unwrap();
+                    socket.write_all(b"\n\n").await.unwrap();
+                });
+            }
+        });
+
+        path.to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_create_and_lookup_invoice() {
+        let path = fake_cln("invoice", |method, params| match method {
+            "invoice" => {
+                assert_eq!(params["amount_msat"], 21_500);
+                assert_eq!(params["description"], "zap");
+                assert_eq!(params["deschashonly"], true);
+                json!({"payment_hash": "aa".repeat(32), "bolt11": "lnbc210n1fake"})
+            }
+            "listinvoices" => {
+                assert_eq!(params["payment_hash"], "aa".repeat(32));
+                json!({"invoices": [{
+                    "payment_hash": "aa".repeat(32),
+                    "status": "paid",
+                    "amount_received_msat": 21_000,
+                    "payment_preimage": "bb".repeat(32),
+                    "updated_index": 1,
+                }]})
+            }
+            _ => panic!("unexpected method {method}"),
+        });
+
+        let cln = ClnClient::new(path);
+        let invoice = cln
+            .create_invoice(InvoiceRequest {
+                amount_msat: 21_500,
+                description: InvoiceDescription::Hashed("zap".into()),
+            })
+            .await
+            .unwrap();
+        assert_eq!(invoice.bolt11, "lnbc210n1fake");
+
+        let payment = cln
+            .lookup_incoming_payment(&invoice.payment_hash)
+            .await
+            .unwrap();
+        assert!(payment.is_paid);
+        assert_eq!(payment.received_msat, 21_000);
+        assert_eq!(payment.preimage, Some("bb".repeat(32)));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_payments() {
+        let path = fake_cln("subscribe", |method, params| match method {
+            // the index is at 5 when we subscribe, and moves by one on every wait
+            "wait" => {
+                assert_eq!(params["subsystem"], "invoices");
+                assert_eq!(params["indexname"], "updated");
+                match params["nextvalue"].as_u64().unwrap() {
+                    0 => json!({"subsystem": "invoices", "updated": 5}),
+                    next => json!({"subsystem": "invoices", "updated": next}),
+                }
+            }
+            "listinvoices" => {
+                assert_eq!(params["index"], "updated");
+                let start = params["start"].as_u64().unwrap();
+                assert!(start > 5, "listed invoices from before we subscribed");
+
+                // one invoice expires in between the paid ones
+                let status = if start == 7 { "expired" } else { "paid" };
+                json!({"invoices": [{
+                    "payment_hash": format!("{start:064x}"),
+                    "status": status,
+                    "amount_received_msat": 1_000,
+                    "updated_index": start,
+                }]})
+            }
+            _ => panic!("unexpected method {method}"),
+        });
+
+        let mut payments = ClnClient::new(path).subscribe_payments().await.unwrap();
+        assert_eq!(
+            payments.recv().await.unwrap().payment_hash,
+            format!("{:064x}", 6)
+        );
+        assert_eq!(
+            payments.recv().await.unwrap().payment_hash,
+            format!("{:064x}", 8)
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use async_trait::async_trait;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use futures_util::StreamExt;
+use hex_conservative::DisplayHex;
+use reqwest::Certificate;
+use reqwest::Client;
+use serde::Deserialize;
+use serde::Deserializer;
+use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::Receiver;
+
+use super::new_preimage;
+use super::BackendError;
+use super::IncomingPayment;
+use super::Invoice;
+use super::InvoiceDescription;
+use super::InvoiceRequest;
+use super::LightningBackend;
+use super::INVOICE_EXPIRY;
+
+#[derive(Clone)]
+/// A client for LND's REST API
+pub struct LndClient {
+    /// A reqwest client that trusts LND's TLS certificate
+    client: Client,
+    /// The hex-encoded macaroon we use to authenticate with LND
+    macaroon: String,
+    /// The host where LND's REST API is listening
+    host: String,
+}
+
+#[derive(Serialize)]
+/// Data sent to LND when we call "POST /v1/invoices"
+struct AddInvoiceRequest {
+    /// The preimage for this invoice, base64-encoded. We pick it so we know it upfront
+    r_preimage: String,
+    /// The invoice amount, in milisats
+    value_msat: String,
+    /// How long this invoice can be paid for, in seconds
+    expiry: String,
+    /// A description for this invoice
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+    /// The sha256 of this invoice's description, base64-encoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description_hash: Option<String>,
+}
+
+impl AddInvoiceRequest {
+    /// Builds the body for a new invoice with this preimage
+    fn new(request: InvoiceRequest, preimage: &[u8; 32]) -> Self {
+        let (memo, description_hash) = match request.description {
+            InvoiceDescription::Text(text) => (Some(text), None),
+            InvoiceDescription::Hashed(text) => (
+                None,
+                Some(BASE64_STANDARD.encode(InvoiceDescription::hash(&text))),
+            ),
+        };
+
+        AddInvoiceRequest {
+            r_preimage: BASE64_STANDARD.encode(preimage),
+            value_msat: request.amount_msat.to_string(),
+            expiry: INVOICE_EXPIRY.to_string(),
+            memo,
+            description_hash,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+/// Data returned from LND when we call "POST /v1/invoices"
+struct AddInvoiceResponse {
+    /// The payment hash for this invoice, base64-encoded
+    r_hash: String,
+    /// The actual bolt11 invoice
+    payment_request: String,
+}
+
+#[derive(Deserialize)]
+/// An invoice, as returned by "GET /v1/invoice/{r_hash}" and the subscription stream
+struct LndInvoice {
+    /// The payment hash for this invoice, base64-encoded
+    r_hash: String,
+    /// The payment preimage for this invoice, base64-encoded
+    #[serde(default)]
+    r_preimage: String,
+    /// One of "OPEN", "SETTLED", "CANCELED" or "ACCEPTED"
+    #[serde(default)]
+    state: String,
+    /// How much we've received for this invoice
+    #[serde(default, deserialize_with = "u64_from_str")]
+    amt_paid_msat: u64,
+}
+
+#[derive(Deserialize)]
+/// Every message in LND's invoice subscription stream is wrapped in one of those
+struct StreamMessage {
+    result: LndInvoice,
+}
+
+/// LND's REST API encodes 64-bits integers as strings
+fn u64_from_str<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value.parse().map_err(serde::de::Error::custom)
+}
+
+/// Turns a base64 string from LND into a lower-case hex string
+fn base64_to_hex(value: &str) -> Result<String, BackendError> {
+    BASE64_STANDARD
+        .decode(value)
+        .map(|bytes| bytes.to_lower_hex_string())
+        .map_err(|e| BackendError::InvalidResponse(e.to_string()))
+}
+
+impl LndInvoice {
+    fn into_incoming_payment(self) -> Result<IncomingPayment, BackendError> {
+        let is_paid = self.state == "SETTLED";
+        let preimage = match (is_paid, self.r_preimage.is_empty()) {
+            (true, false) => Some(base64_to_hex(&self.r_preimage)?),
+            _ => None,
+        };
+
+        Ok(IncomingPayment {
+            payment_hash: base64_to_hex(&self.r_hash)?,
+            is_paid,
+            received_msat: self.amt_paid_msat,
+            preimage,
+        })
+    }
+}
+
+impl LndClient {
+    /// Creates a new LND client
+    ///
+    /// `macaroon_path` should point to a macaroon that can create and read invoices, like
+    /// `invoice.macaroon`, and `cert_path` to LND's `tls.cert`.
+    pub fn new(host: String, macaroon_path: &str, cert_path: &str) -> Result<Self, BackendError> {
+        let macaroon = std::fs::read(macaroon_path)
+            .map_err(|e| BackendError::Connection(format!("can't read macaroon: {e}")))?;
+        let cert = std::fs::read(cert_path)
+            .map_err(|e| BackendError::Connection(format!("can't read tls cert: {e}")))?;
+        let cert = Certificate::from_pem(&cert)?;
+        let client = Client::builder().add_root_certificate(cert).build()?;
+
+        Ok(Self {
+            client,
+            macaroon: macaroon.to_lower_hex_string(),
+            host,
+        })
+    }
+}
+
+#[async_trait]
+impl LightningBackend for LndClient {
+    async fn create_invoice(&self, request: InvoiceRequest) -> Result<Invoice, BackendError> {
+        let preimage = new_preimage();
+        let body = AddInvoiceRequest::new(request, &preimage);
+
+        let res = self
+            .client
+            .post(format!("https://{}/v1/invoices", self.host))
+            .header("Grpc-Metadata-macaroon", &self.macaroon)
+            .json(&body)
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        let response: AddInvoiceResponse = serde_json::from_str(&res)?;
+        Ok(Invoice {
+            payment_hash: base64_to_hex(&response.r_hash)?,
+            bolt11: response.payment_request,
+            preimage: Some(preimage.to_lower_hex_string()),
+        })
+    }
+
+    async fn lookup_incoming_payment(
+        &self,
+        payment_hash: &str,
+    ) -> Result<IncomingPayment, BackendError> {
+        let res = self
+            .client
+            .get(format!("https://{}/v1/invoice/{}", self.host, payment_hash))
+            .header("Grpc-Metadata-macaroon", &self.macaroon)
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        let invoice: LndInvoice = serde_json::from_str(&res)?;
+        invoice.into_incoming_payment()
+    }
+
+    async fn subscribe_payments(&self) -> Result<Receiver<IncomingPayment>, BackendError> {
+        let res = self
+            .client
+            .get(format!("https://{}/v1/invoices/subscribe", self.host))
+            .header("Grpc-Metadata-macaroon", &self.macaroon)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let (sender, receiver) = channel(1024);
+        tokio::task::spawn(async move {
+            let mut stream = res.bytes_stream();
+            let mut buffer = Vec::new();
+
+            // LND sends one json object per line, but a line may be split across chunks
+            while let Some(Ok(chunk)) = stream.next().await {
+                buffer.extend_from_slice(&chunk);
+                while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=end).collect();
+                    let Ok(message) = serde_json::from_slice::<StreamMessage>(&line) else {
+                        continue;
+                    };
+
+                    let payment = match message.result.into_incoming_payment() {
+                        Ok(payment) if payment.is_paid => payment,
+                        _ => continue,
+                    };
+
+                    if sender.send(payment).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine;
+    use serde_json::json;
+
+    use super::AddInvoiceRequest;
+    use crate::backend::InvoiceDescription;
+    use crate::backend::InvoiceRequest;
+
+    #[test]
+    fn test_add_invoice_request() {
+        // LND takes milisats as-is, so odd amounts shouldn't be rounded to sats
+        let request = InvoiceRequest {
+            amount_msat: 21_500,
+            description: InvoiceDescription::Hashed("[[\"text/plain\", \"john\"]]".into()),
+        };
+        let body = AddInvoiceRequest::new(request, &[1; 32]);
+        assert_eq!(
+            serde_json::to_value(&body).unwrap(),
+            json!({
+                "r_preimage": BASE64_STANDARD.encode([1; 32]),
+                "value_msat": "21500",
+                "expiry": "3600",
+                "description_hash": "WYDpalUo3BclAyy5RahCLBXgpyuWNS1WOTzPociZqvc=",
+            })
+        );
+
+        let request = InvoiceRequest {
+            amount_msat: 1_000,
+            description: InvoiceDescription::Text("zap".into()),
+        };
+        let body = AddInvoiceRequest::new(request, &[1; 32]);
+        assert_eq!(
+            serde_json::to_value(&body).unwrap(),
+            json!({
+                "r_preimage": BASE64_STANDARD.encode([1; 32]),
+                "value_msat": "1000",
+                "expiry": "3600",
+                "memo": "zap",
+            })
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! The lightning wallets we can use to receive payments
+//!
+//! Everything that talks with a lightning node goes through the [LightningBackend] trait, so
+//! the http api and the zap handler don't need to know anything about a specific wallet's wire
+//! format.
+
+pub mod cln;
+pub mod lnd;
+pub mod phoenixd;
+
+use std::fmt::Display;
+
+use async_trait::async_trait;
+use sha2::Digest;
+use sha2::Sha256;
+use tokio::sync::mpsc::Receiver;
+
+#[derive(Debug)]
+/// The errors returned by a lightning backend
+pub enum BackendError {
+    /// We couldn't reach our backend, or the connection died while talking to it
+    Connection(String),
+    /// The backend returned something we don't understand
+    InvalidResponse(String),
+    /// The backend can't create an invoice for this many milisats, e.g. it only takes whole sats
+    UnsupportedAmount(u64),
+}
+
+impl Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendError::Connection(e) => write!(f, "can't talk with backend: {e}"),
+            BackendError::InvalidResponse(e) => write!(f, "invalid response from backend: {e}"),
+            BackendError::UnsupportedAmount(msat) => {
+                write!(f, "backend can't create an invoice for {msat} msat")
+            }
+        }
+    }
+}
+
+impl From<reqwest::Error> for BackendError {
+    fn from(value: reqwest::Error) -> Self {
+        BackendError::Connection(value.to_string())
+    }
+}
+
+impl From<serde_json::Error> for BackendError {
+    fn from(value: serde_json::Error) -> Self {
+        BackendError::InvalidResponse(value.to_string())
+    }
+}
+
+/// What an invoice should say about itself
+pub enum InvoiceDescription {
+    /// A description that goes in the invoice as-is
+    #[allow(unused)]
+    Text(String),
+    /// The invoice only commits to the sha256 of this string.
+    ///
+    /// LNURL-pay (LUD-06) wants the hash of the metadata, and zaps (NIP-57) the hash of the zap
+    /// request. Some backends need the hash, others want the full string, so we give them the
+    /// full string.
+    Hashed(String),
+}
+
+impl InvoiceDescription {
+    /// The sha256 of a [InvoiceDescription::Hashed] description
+    pub fn hash(description: &str) -> [u8; 32] {
+        Sha256::digest(description.as_bytes()).into()
+    }
+}
+
+/// How long our invoices can be paid for, in seconds. We tell every backend, so an invoice
+/// can't be paid after we've marked it expired in our ledger
+pub const INVOICE_EXPIRY: u64 = 3600;
+
+/// Returns a new random preimage, for backends that let us choose it
+pub fn new_preimage() -> [u8; 32] {
+    rand::random()
+}
+
+/// What we need to create a new invoice
+pub struct InvoiceRequest {
+    /// The invoice amount, in milisats. Backends that can't honor it exactly must refuse it
+    /// with [BackendError::UnsupportedAmount], never round it
+    pub amount_msat: u64,
+    /// A description that will be committed to in this invoice
+    pub description: InvoiceDescription,
+}
+
+#[derive(Clone, Debug)]
+/// An invoice created by our backend
+pub struct Invoice {
+    /// The payment hash for this invoice, hex-encoded.
+    ///
+    /// We use this to find out whether this invoice was paid or not
+    pub payment_hash: String,
+    /// The actual bolt11 invoice
+    pub bolt11: String,
+    /// The preimage for this invoice, hex-encoded. It's revealed to the payer once they pay,
+    /// so we may use it as a key for things only the payer should see (LUD-10).
+    ///
+    /// Only backends that let us pick the preimage know it upfront. For the others, it's given
+    /// by [LightningBackend::lookup_incoming_payment]
+    pub preimage: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+/// The state of a payment we've asked for
+pub struct IncomingPayment {
+    /// The payment hash for this payment, hex-encoded
+    pub payment_hash: String,
+    /// Whether this payment was already received
+    pub is_paid: bool,
+    /// How much we've actually received, in milisats
+    pub received_msat: u64,
+    /// The payment preimage, hex-encoded, if the backend gives it to us
+    pub preimage: Option<String>,
+}
+
+#[async_trait]
+/// A lightning wallet that can create invoices and tell us when they get paid
+pub trait LightningBackend: Send + Sync {
+    /// Asks the backend for a new invoice
+    async fn create_invoice(&self, request: InvoiceRequest) -> Result<Invoice, BackendError>;
+
+    /// Returns the current state of an invoice we've created before
+    async fn lookup_incoming_payment(
+        &self,
+        payment_hash: &str,
+    ) -> Result<IncomingPayment, BackendError>;
+
+    /// Subscribes to payments received by this backend
+    ///
+    /// Every settled payment is sent through the returned channel. If the subscription breaks,
+    /// the channel is closed and callers should subscribe again.
+    async fn subscribe_payments(&self) -> Result<Receiver<IncomingPayment>, BackendError>;
+}
@@ -0,0 +1,248 @@
+use async_trait::async_trait;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use futures_util::StreamExt;
+use hex_conservative::DisplayHex;
+use reqwest::Client;
+use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::Receiver;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+use super::BackendError;
+use super::IncomingPayment;
+use super::Invoice;
+use super::InvoiceDescription;
+use super::InvoiceRequest;
+use super::LightningBackend;
+use super::INVOICE_EXPIRY;
+
+#[derive(Clone)]
+/// A struct that holds all data needed to connect with a running phoenixd,
+/// the actual lightning wallet powering this application
+pub struct PhoenixdClient {
+    /// A reqwest client used to send phoenixd requests
+    pub client: Client,
+    /// The password we use to authenticate with phoenixd.
+    ///
+    /// You can find this in $PHOENIXD_DATA_DIR/phoenixd.conf
+    pub password: String,
+    /// The host where phoenixd is running
+    pub host: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+/// Data returned from phoenixd when we call "getInvoice"
+///
+/// The most importanti info here is the "serialized" field, that contains the bolt11
+/// invoice
+pub struct GetInvoiceResponse {
+    #[serde(rename = "amountSat")]
+    /// The invoice amount, in sats
+    pub amount_sat: u64,
+    #[serde(rename = "paymentHash")]
+    /// The payment hash for this invoice.
+    ///
+    /// We may need this information to keep track of whether this invoice was paid or not
+    pub payment_hash: String,
+    /// The actual bolt11 invoice
+    pub serialized: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Data returned from phoenixd when we call "payments/incoming/{hash}"
+pub struct IncomingPaymentInfo {
+    #[serde(rename = "paymentHash")]
+    pub payment_hash: String,
+    pub preimage: String,
+    pub description: String,
+    pub invoice: String,
+    #[serde(rename = "isPaid")]
+    pub is_paid: bool,
+    #[serde(rename = "receivedSat")]
+    pub received_sat: u64,
+    pub fees: u64,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+}
+
+#[derive(Deserialize)]
+/// A notification sent by phoenixd through its websocket
+struct WebsocketNotification {
+    /// What kind of notification is this. We only care about "payment_received"
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "amountSat", default)]
+    amount_sat: u64,
+    #[serde(rename = "paymentHash", default)]
+    payment_hash: String,
+}
+
+/// Converts an invoice amount to the whole sats phoenixd wants, refusing amounts that would need
+/// rounding: rounding down under-charges, and rounding up asks the payer for more than they agreed
+fn amount_sat(amount_msat: u64) -> Result<u64, BackendError> {
+    match amount_msat {
+        0 => Err(BackendError::UnsupportedAmount(amount_msat)),
+        msat if msat % 1_000 != 0 => Err(BackendError::UnsupportedAmount(amount_msat)),
+        msat => Ok(msat / 1_000),
+    }
+}
+
+#[async_trait]
+impl LightningBackend for PhoenixdClient {
+    async fn create_invoice(&self, request: InvoiceRequest) -> Result<Invoice, BackendError> {
+        let amount = amount_sat(request.amount_msat)?.to_string();
+        let description = match &request.description {
+            InvoiceDescription::Text(text) => ("description", text.clone()),
+            InvoiceDescription::Hashed(text) => (
+                "descriptionHash",
+                InvoiceDescription::hash(text).to_lower_hex_string(),
+            ),
+        };
+        let values = [
+            description,
+            ("amountSat", amount),
+            ("expirySeconds", INVOICE_EXPIRY.to_string()),
+        ];
+
+        let res = self
+            .client
+            .post(format!("http://{}/createinvoice", self.host))
+            .basic_auth("".to_string(), Some(&self.password))
+            .form(&values)
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        let response: GetInvoiceResponse = serde_json::from_str(&res)?;
+
+        // phoenixd picks the preimage itself, and only tells it when we look the payment up
+        Ok(Invoice {
+            payment_hash: response.payment_hash,
+            bolt11: response.serialized,
+            preimage: None,
+        })
+    }
+
+    async fn lookup_incoming_payment(
+        &self,
+        payment_hash: &str,
+    ) -> Result<IncomingPayment, BackendError> {
+        let res = self
+            .client
+            .get(format!(
+                "http://{}/payments/incoming/{}",
+                self.host, payment_hash
+            ))
+            .basic_auth("".to_string(), Some(&self.password))
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        let res: IncomingPaymentInfo = serde_json::from_str(&res)?;
+        Ok(IncomingPayment {
+            payment_hash: res.payment_hash,
+            is_paid: res.is_paid,
+            received_msat: res.received_sat * 1_000,
+            preimage: Some(res.preimage),
+        })
+    }
+
+    async fn subscribe_payments(&self) -> Result<Receiver<IncomingPayment>, BackendError> {
+        let mut request = format!("ws://{}/websocket", self.host)
+            .into_client_request()
+            .map_err(|e| BackendError::Connection(e.to_string()))?;
+        let auth = BASE64_STANDARD.encode(format!(":{}", self.password));
+        request.headers_mut().insert(
+            "Authorization",
+            format!("Basic {auth}")
+                .parse()
+                .map_err(|_| BackendError::Connection("invalid password".into()))?,
+        );
+
+        let (mut ws_stream, _) = connect_async(request)
+            .await
+            .map_err(|e| BackendError::Connection(e.to_string()))?;
+
+        let (sender, receiver) = channel(1024);
+        tokio::task::spawn(async move {
+            // tungstenite answers pings for us while we read
+            while let Some(Ok(message)) = ws_stream.next().await {
+                let Message::Text(text) = message else {
+                    continue;
+                };
+
+                let Ok(notification) = serde_json::from_str::<WebsocketNotification>(&text) else {
+                    continue;
+                };
+
+                if notification.kind != "payment_received" {
+                    continue;
+                }
+
+                let payment = IncomingPayment {
+                    payment_hash: notification.payment_hash,
+                    is_paid: true,
+                    received_msat: notification.amount_sat * 1_000,
+                    preimage: None,
+                };
+
+                if sender.send(payment).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::amount_sat;
+    use super::PhoenixdClient;
+    use crate::backend::BackendError;
+    use crate::backend::InvoiceDescription;
+    use crate::backend::InvoiceRequest;
+    use crate::backend::LightningBackend;
+
+    #[test]
+    fn test_amount_sat() {
+        assert_eq!(amount_sat(1_000).unwrap(), 1);
+        assert_eq!(amount_sat(21_000).unwrap(), 21);
+        assert_eq!(
+            amount_sat(u64::MAX - u64::MAX % 1_000).unwrap(),
+            u64::MAX / 1_000
+        );
+
+        for msat in [0, 1, 999, 1_001, 1_500, 1_999, u64::MAX] {
+            assert!(matches!(
+                amount_sat(msat),
+                Err(BackendError::UnsupportedAmount(amount)) if amount == msat
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refuse_msat_invoice() {
+        // nothing listens here, so this only passes if we refuse before talking with phoenixd
+        let phoenixd = PhoenixdClient {
+            client: reqwest::Client::default(),
+            password: "password".into(),
+            host: "127.0.0.1:1".into(),
+        };
+        let result = phoenixd
+            .create_invoice(InvoiceRequest {
+                amount_msat: 21_500,
+                description: InvoiceDescription::Hashed("zap".into()),
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(BackendError::UnsupportedAmount(21_500))
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+//! A minimal bech32 (BIP-173) encoder and decoder
+//!
+//! Nostr keys (npub) and lnurls are both bech32 strings. Unlike BIP-173, we don't enforce the
+//! 90 chars limit, since lnurls are usually longer than that.
+
+use std::fmt::Debug;
+use std::fmt::Display;
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The errors we may find while decoding a bech32 string
+pub enum Bech32Error {
+    /// The string doesn't have a separator, or the human-readable part is empty
+    MissingSeparator,
+    /// The string mixes upper and lower case chars
+    MixedCase,
+    /// The string has a char that isn't part of the bech32 charset
+    InvalidChar(char),
+    /// The checksum doesn't match
+    InvalidChecksum,
+    /// The data part can't be converted back to bytes
+    InvalidPadding,
+}
+
+impl Display for Bech32Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self, f)
+    }
+}
+
+fn polymod(values: impl Iterator<Item = u8>) -> u32 {
+    let mut chk = 1_u32;
+    for value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|b| b & 31));
+    expanded
+}
+
+/// Regroups a slice of `from`-bits values into `to`-bits values.
+///
+/// When encoding we `pad` the last group with zeros, when decoding that padding must be there
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, Bech32Error> {
+    let mut acc = 0_u32;
+    let mut bits = 0_u32;
+    let max = (1 << to) - 1;
+    let mut ret = Vec::new();
+
+    for value in data {
+        acc = (acc << from) | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            ret.push(((acc >> bits) & max) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            ret.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max) != 0 {
+        return Err(Bech32Error::InvalidPadding);
+    }
+
+    Ok(ret)
+}
+
+/// Encodes `data` as a lowercase bech32 string, with `hrp` as its human-readable part
+pub fn encode(hrp: &str, data: &[u8]) -> String {
+    let hrp = hrp.to_lowercase();
+    let mut values = convert_bits(data, 8, 5, true).expect("padding never fails");
+
+    let checksum_input = hrp_expand(&hrp)
+        .into_iter()
+        .chain(values.iter().copied())
+        .chain([0; 6]);
+    let checksum = polymod(checksum_input) ^ 1;
+    values.extend((0..6).map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8));
+
+    let data: String = values
+        .into_iter()
+        .map(|value| CHARSET[value as usize] as char)
+        .collect();
+    format!("{hrp}1{data}")
+}
+
+/// Decodes a bech32 string, returning its human-readable part and data
+pub fn decode(encoded: &str) -> Result<(String, Vec<u8>), Bech32Error> {
+    let has_lower = encoded.chars().any(|ch| ch.is_ascii_lowercase());
+    let has_upper = encoded.chars().any(|ch| ch.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err(Bech32Error::MixedCase);
+    }
+
+    let encoded = encoded.to_lowercase();
+    let separator = encoded.rfind('1').ok_or(Bech32Error::MissingSeparator)?;
+    if separator == 0 || separator + 7 > encoded.len() {
+        return Err(Bech32Error::MissingSeparator);
+    }
+
+    let (hrp, data) = encoded.split_at(separator);
+    if let Some(ch) = hrp.chars().find(|ch| !(33..=126).contains(&(*ch as u32))) {
+        return Err(Bech32Error::InvalidChar(ch));
+    }
+
+    let data = data[1..]
+        .chars()
+        .map(|ch| {
+            CHARSET
+                .iter()
+                .position(|c| *c as char == ch)
+                .map(|value| value as u8)
+                .ok_or(Bech32Error::InvalidChar(ch))
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+
+    let values = hrp_expand(hrp).into_iter().chain(data.iter().copied());
+    if polymod(values) != 1 {
+        return Err(Bech32Error::InvalidChecksum);
+    }
+
+    let data = convert_bits(&data[..data.len() - 6], 5, 8, false)?;
+    Ok((hrp.to_owned(), data))
+}
+
+#[cfg(test)]
+mod test {
+    use hex_conservative::DisplayHex;
+
+    use super::decode;
+    use super::encode;
+    use super::Bech32Error;
+
+    #[test]
+    fn test_npub() {
+        // from NIP-19
+        let npub = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+        let (hrp, data) = decode(npub).unwrap();
+
+        assert_eq!(hrp, "npub");
+        assert_eq!(
+            data.to_lower_hex_string(),
+            "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e"
+        );
+    }
+
+    #[test]
+    fn test_lnurl() {
+        // from LUD-01
+        let lnurl = "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS";
+        let url = "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df";
+
+        let (hrp, data) = decode(lnurl).unwrap();
+        assert_eq!(hrp, "lnurl");
+        assert_eq!(data, url.as_bytes());
+
+        assert_eq!(encode("lnurl", url.as_bytes()), lnurl.to_lowercase());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for len in 0..=64 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 + 11) as u8).collect();
+            let encoded = encode("test", &data);
+            assert_eq!(decode(&encoded).unwrap(), ("test".to_owned(), data));
+            assert_eq!(
+                decode(&encoded.to_uppercase()).unwrap().0,
+                "test".to_owned()
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(
+            decode("npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjpth"),
+            Err(Bech32Error::InvalidChecksum)
+        );
+        assert_eq!(decode("Npub1qqqqqqq"), Err(Bech32Error::MixedCase));
+        assert_eq!(decode("qqqqqqqqqq"), Err(Bech32Error::MissingSeparator));
+        assert_eq!(decode("npub1bqqqqqqq"), Err(Bech32Error::InvalidChar('b')));
+    }
+}
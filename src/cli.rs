@@ -1,28 +1,118 @@
 use secp256k1::SecretKey;
 
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+/// The lightning wallets we know how to talk with
+pub enum Backend {
+    /// A phoenixd instance, talking through its http API
+    Phoenixd,
+    /// An LND node, talking through its REST API
+    Lnd,
+    /// A Core Lightning node, talking through its JSON-RPC socket
+    Cln,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+/// Where we keep our users
+pub enum UserStoreKind {
+    /// A directory with one json file per user, see "--users-dir"
+    Dir,
+    /// The users table in our database, see "--database"
+    Sqlite,
+}
+
 #[derive(clap::Parser)]
 pub struct Cli {
     /// The password to our phoenix instance. You can find this in "~/.phoenix/phoenix.conf
-    pub phoenixd_password: String,
+    ///
+    /// Only required if you're using phoenixd as backend
+    #[arg(
+        required_unless_present("backend"),
+        required_if_eq("backend", "phoenixd")
+    )]
+    pub phoenixd_password: Option<String>,
+
+    /// Which lightning wallet we should use to create invoices. Defaults to phoenixd
+    #[arg(short, long, value_enum)]
+    pub backend: Option<Backend>,
 
-    /// The path where we can find users to return
+    /// Where we keep our users. Defaults to a users dir
+    #[arg(long, value_enum, value_name = "STORE")]
+    pub user_store: Option<UserStoreKind>,
+
+    /// The path where we can find users to return, if they are kept in a directory
     ///
     /// This should be in absolute path and must exist. Defaults to "./users/"
     #[arg(short, long, value_name = "FILE")]
     pub users_dir: Option<String>,
 
+    /// Where we keep our database. Defaults to "./ln-address.db"
+    #[arg(long, value_name = "FILE")]
+    pub database: Option<String>,
+
+    /// A nostr public key (hex or npub) allowed to use the admin endpoints, authenticating with
+    /// NIP-98. Can be used multiple times
+    ///
+    /// If none is given, the admin endpoints are disabled
+    #[arg(long = "admin", value_name = "NPUB")]
+    pub admins: Vec<String>,
+
     /// The network address for your phoenixd (only if you've changed it)
     #[arg(short = 'a', long, value_name = "ADDRESS")]
     pub phoenixd_address: Option<String>,
 
+    /// The network address for LND's REST API. LND defaults to "127.0.0.1:8080", which is also
+    /// our own API's default port, so one of them must be moved
+    #[arg(long, value_name = "ADDRESS", required_if_eq("backend", "lnd"))]
+    pub lnd_address: Option<String>,
+
+    /// The path to a macaroon that can create and read invoices, like LND's "invoice.macaroon"
+    #[arg(long, value_name = "FILE", required_if_eq("backend", "lnd"))]
+    pub lnd_macaroon: Option<String>,
+
+    /// The path to LND's TLS certificate, usually "~/.lnd/tls.cert"
+    #[arg(long, value_name = "FILE", required_if_eq("backend", "lnd"))]
+    pub lnd_cert: Option<String>,
+
+    /// The path to CLN's JSON-RPC socket, usually "~/.lightning/bitcoin/lightning-rpc"
+    #[arg(long, value_name = "FILE", required_if_eq("backend", "cln"))]
+    pub cln_rpc_path: Option<String>,
+
     /// The ip address we should listen to
     #[arg(short = 'H', long, value_name = "ADDRESS")]
     pub api_host: Option<String>,
 
-    /// The port we should listen to
+    /// The port we should listen to. Defaults to 8080
     #[arg(short = 'P', long, value_name = "PORT")]
     pub api_port: Option<u16>,
 
+    /// The domain our lightning addresses live in, like "smith.com" for "john@smith.com"
+    #[arg(short, long, value_name = "DOMAIN")]
+    pub domain: Option<String>,
+
+    /// The public url where this server can be reached, like "https://smith.com"
+    ///
+    /// Defaults to "https://<DOMAIN>" if a domain is set, otherwise we use the address each
+    /// request was sent to
+    #[arg(long, value_name = "URL")]
+    pub base_url: Option<String>,
+
+    /// The smallest amount users can receive, in milisats, unless they set their own
+    /// minSendable. Must be a whole number of sats. Defaults to 1000 (1 sat)
+    #[arg(long, value_name = "MSAT")]
+    pub min_sendable: Option<u64>,
+
+    /// The biggest amount users can receive, in milisats, unless they set their own
+    /// maxSendable. Defaults to 10000000 (10k sats)
+    #[arg(long, value_name = "MSAT")]
+    pub max_sendable: Option<u64>,
+
+    /// A relay we should publish zap receipts to. Can be used multiple times
+    ///
+    /// Receipts also go to the relays listed in each zap request. Defaults to a few popular
+    /// public relays
+    #[arg(short, long = "relay", value_name = "URL")]
+    pub relays: Vec<String>,
+
     /// A secret key used for signing nostr receipts
     #[arg(short = 's', long, value_name = "KEY")]
     pub secret_key: SecretKey,
@@ -0,0 +1,702 @@
+//! Our local database, used to keep anything that should survive a restart
+//!
+//! This is a single SQLite file. The schema is created and upgraded by [MIGRATIONS], using
+//! SQLite's `user_version` to know which of them already ran.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use rusqlite::params;
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::Row;
+
+use crate::nostr::nip19::parse_pubkey;
+use crate::nostr::nostr_event::Event;
+use crate::nostr::zap_handler::PendingZap;
+
+/// Every change to our schema, in order. Never change an existing migration, add a new one
+/// instead
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE inflight_zaps (
+        payment_hash TEXT PRIMARY KEY NOT NULL,
+        bolt11 TEXT NOT NULL,
+        zap_request TEXT NOT NULL,
+        recipient TEXT NOT NULL,
+        lnurl TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );",
+    "CREATE TABLE payments (
+        payment_hash TEXT PRIMARY KEY NOT NULL,
+        username TEXT NOT NULL,
+        amount_msat INTEGER NOT NULL,
+        bolt11 TEXT NOT NULL,
+        zap_request TEXT,
+        status TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        settled_at INTEGER
+    );
+    CREATE INDEX payments_by_user ON payments (username, created_at);
+    CREATE INDEX payments_by_status ON payments (status, created_at);",
+    "CREATE TABLE users (
+        username TEXT PRIMARY KEY NOT NULL,
+        data TEXT NOT NULL
+    );",
+    "ALTER TABLE payments ADD COLUMN comment TEXT;",
+    "ALTER TABLE payments ADD COLUMN payer_data TEXT;",
+    "CREATE TABLE zap_receipts (
+        event_id TEXT PRIMARY KEY NOT NULL,
+        event TEXT NOT NULL,
+        relays TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );",
+    "ALTER TABLE payments ADD COLUMN preimage TEXT;",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Where a payment is in its life
+pub enum PaymentStatus {
+    /// We gave out an invoice, but it wasn't paid yet
+    Pending,
+    /// The invoice got paid
+    Settled,
+    /// The invoice wasn't paid in time, and we stopped watching it
+    Expired,
+}
+
+impl PaymentStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Settled => "settled",
+            PaymentStatus::Expired => "expired",
+        }
+    }
+
+    fn parse(status: &str) -> Option<Self> {
+        match status {
+            "pending" => Some(PaymentStatus::Pending),
+            "settled" => Some(PaymentStatus::Settled),
+            "expired" => Some(PaymentStatus::Expired),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// An invoice created by our callback, and what happened to it
+pub struct Payment {
+    /// Who is being paid
+    pub username: String,
+    /// How much was requested, in milisats
+    pub amount_msat: u64,
+    /// A hash used to identify this payment
+    pub payment_hash: String,
+    /// The invoice we gave out
+    pub bolt11: String,
+    /// The zap request for this payment, if it's a zap
+    pub zap_request: Option<String>,
+    /// The comment sent with this payment (LUD-12)
+    pub comment: Option<String>,
+    /// The json identifying the payer, exactly as they sent it (LUD-18)
+    pub payer_data: Option<String>,
+    /// Whether it was paid
+    pub status: PaymentStatus,
+    /// When the invoice was created, as a unix timestamp
+    pub created_at: u64,
+    /// When we saw the payment, as a unix timestamp
+    pub settled_at: Option<u64>,
+    /// The invoice's preimage, hex-encoded. Only kept once it's paid, and only if our backend
+    /// told us
+    pub preimage: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+/// Which payments we want from [Database::list_payments]
+pub struct PaymentFilter {
+    /// Only payments to this user
+    pub user: Option<String>,
+    /// Only payments with this status
+    pub status: Option<PaymentStatus>,
+    /// Only payments created at or after this unix timestamp
+    pub since: Option<u64>,
+    /// Only payments created before this unix timestamp
+    pub until: Option<u64>,
+    /// How many payments to return
+    pub limit: Option<u32>,
+    /// How many payments to skip, for pagination
+    pub offset: Option<u32>,
+}
+
+/// How many payments we return if a limit isn't given
+pub const DEFAULT_PAYMENTS_LIMIT: u32 = 50;
+
+/// The most payments we return at once
+pub const MAX_PAYMENTS_LIMIT: u32 = 500;
+
+#[derive(Clone)]
+/// A handle to our database. Cloning it is cheap, and every clone uses the same connection
+pub struct Database {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    /// Opens the database at `path`, creating and upgrading it if needed
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            conn.execute_batch(migration)?;
+            conn.pragma_update(None, "user_version", i + 1)?;
+        }
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn
+            .lock()
+            .expect("a thread panicked while using the database")
+    }
+
+    /// Saves a zap that hasn't been paid yet
+    pub fn insert_inflight_zap(&self, zap: &PendingZap) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO inflight_zaps
+                (payment_hash, bolt11, zap_request, recipient, lnurl, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                zap.payment_hash,
+                zap.bolt11,
+                zap.description,
+                zap.receiver.to_string(),
+                zap.lnurl,
+                zap.created_at,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Forgets about a zap, because it was paid or expired
+    pub fn remove_inflight_zap(&self, payment_hash: &str) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "DELETE FROM inflight_zaps WHERE payment_hash = ?1",
+            params![payment_hash],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns every zap we've saved, skipping (and logging) the ones we can't read back
+    pub fn load_inflight_zaps(&self) -> rusqlite::Result<Vec<PendingZap>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT payment_hash, bolt11, zap_request, recipient, lnurl, created_at
+             FROM inflight_zaps ORDER BY created_at",
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, u64>(5)?,
+            ))
+        })?;
+
+        let mut zaps = Vec::new();
+        for row in rows {
+            let (payment_hash, bolt11, description, recipient, lnurl, created_at) = row?;
+            let event = serde_json::from_str::<Event>(&description).ok();
+            let sender = event.as_ref().and_then(|event| parse_pubkey(&event.pubkey));
+            let receiver = parse_pubkey(&recipient);
+
+            let (Some(event), Some(sender), Some(receiver)) = (event, sender, receiver) else {
+                println!("ignoring invalid inflight zap {payment_hash}");
+                continue;
+            };
+
+            zaps.push(PendingZap {
+                relays: PendingZap::relays_from_request(&event),
+                sender,
+                receiver,
+                bolt11,
+                payment_hash,
+                event,
+                description,
+                lnurl,
+                created_at,
+            });
+        }
+
+        Ok(zaps)
+    }
+
+    /// Replaces a paid zap with its signed receipt, which we keep until some relay accepts it
+    pub fn save_zap_receipt(
+        &self,
+        payment_hash: &str,
+        receipt: &Event,
+        relays: &[String],
+    ) -> rusqlite::Result<()> {
+        let event = serde_json::to_string(receipt).expect("events always serialize");
+        let relays = serde_json::to_string(relays).expect("strings always serialize");
+
+        // both at once, so a crash in between can't lose the receipt or publish it twice
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO zap_receipts (event_id, event, relays, created_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![receipt.id, event, relays, receipt.created_at],
+        )?;
+        tx.execute(
+            "DELETE FROM inflight_zaps WHERE payment_hash = ?1",
+            params![payment_hash],
+        )?;
+        tx.commit()
+    }
+
+    /// Forgets about a zap receipt, because a relay accepted it or it's too old
+    pub fn remove_zap_receipt(&self, event_id: &str) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "DELETE FROM zap_receipts WHERE event_id = ?1",
+            params![event_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns every receipt no relay accepted yet, with the extra relays it should go to
+    pub fn load_zap_receipts(&self) -> rusqlite::Result<Vec<(Event, Vec<String>)>> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT event_id, event, relays FROM zap_receipts ORDER BY created_at")?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+
+        let mut receipts = Vec::new();
+        for row in rows {
+            let (event_id, event, relays) = row?;
+            let event = serde_json::from_str::<Event>(&event).ok();
+            let relays = serde_json::from_str::<Vec<String>>(&relays).ok();
+
+            let (Some(event), Some(relays)) = (event, relays) else {
+                println!("ignoring invalid zap receipt {event_id}");
+                continue;
+            };
+
+            receipts.push((event, relays));
+        }
+
+        Ok(receipts)
+    }
+
+    /// Returns a user's json, or None if we don't have such user
+    pub fn get_user(&self, username: &str) -> rusqlite::Result<Option<String>> {
+        self.conn()
+            .query_row(
+                "SELECT data FROM users WHERE username = ?1",
+                params![username],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Creates a user, unless it already exists. Returns whether it was created
+    pub fn insert_user(&self, username: &str, data: &str) -> rusqlite::Result<bool> {
+        let inserted = self.conn().execute(
+            "INSERT OR IGNORE INTO users (username, data) VALUES (?1, ?2)",
+            params![username, data],
+        )?;
+
+        Ok(inserted > 0)
+    }
+
+    /// Creates or replaces a user
+    pub fn put_user(&self, username: &str, data: &str) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO users (username, data) VALUES (?1, ?2)",
+            params![username, data],
+        )?;
+
+        Ok(())
+    }
+
+    /// Removes a user, returning whether it existed
+    pub fn delete_user(&self, username: &str) -> rusqlite::Result<bool> {
+        let deleted = self
+            .conn()
+            .execute("DELETE FROM users WHERE username = ?1", params![username])?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Returns every user's name and json, sorted by name
+    pub fn list_users(&self) -> rusqlite::Result<Vec<(String, String)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT username, data FROM users ORDER BY username")?;
+        let users = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect();
+
+        users
+    }
+
+    /// Records an invoice we just gave out
+    pub fn insert_payment(&self, payment: &Payment) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT INTO payments
+                (payment_hash, username, amount_msat, bolt11, zap_request, status, created_at,
+                 settled_at, comment, payer_data, preimage)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                payment.payment_hash,
+                payment.username,
+                payment.amount_msat,
+                payment.bolt11,
+                payment.zap_request,
+                payment.status.as_str(),
+                payment.created_at,
+                payment.settled_at,
+                payment.comment,
+                payment.payer_data,
+                payment.preimage,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Marks a payment as settled, returning it. Returns None if we don't know about this
+    /// payment, or if it was already settled
+    ///
+    /// Expired payments can still be settled, in case they get paid after we stopped watching
+    pub fn settle_payment(
+        &self,
+        payment_hash: &str,
+        settled_at: u64,
+        preimage: Option<&str>,
+    ) -> rusqlite::Result<Option<Payment>> {
+        self.conn()
+            .query_row(
+                &format!(
+                    "UPDATE payments SET status = 'settled', settled_at = ?2, preimage = ?3
+                     WHERE payment_hash = ?1 AND status != 'settled'
+                     RETURNING {PAYMENT_COLUMNS}"
+                ),
+                params![payment_hash, settled_at, preimage],
+                payment_from_row,
+            )
+            .optional()
+    }
+
+    /// Saves the preimage for a settled payment, if our backend didn't tell it when it was paid
+    pub fn save_preimage(&self, payment_hash: &str, preimage: &str) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "UPDATE payments SET preimage = ?2 WHERE payment_hash = ?1 AND status = 'settled'",
+            params![payment_hash, preimage],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns a payment by its hash
+    pub fn get_payment(&self, payment_hash: &str) -> rusqlite::Result<Option<Payment>> {
+        self.conn()
+            .query_row(
+                &format!("SELECT {PAYMENT_COLUMNS} FROM payments WHERE payment_hash = ?1"),
+                params![payment_hash],
+                payment_from_row,
+            )
+            .optional()
+    }
+
+    /// Marks every payment still pending that was created before `before` as expired
+    pub fn expire_payments(&self, before: u64) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "UPDATE payments SET status = 'expired'
+             WHERE status = 'pending' AND created_at < ?1",
+            params![before],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns the payment hash of every payment that is still pending
+    pub fn pending_payments(&self) -> rusqlite::Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT payment_hash FROM payments WHERE status = 'pending'")?;
+        let hashes = stmt.query_map([], |row| row.get(0))?;
+
+        hashes.collect()
+    }
+
+    /// Returns the payments matching `filter`, newest first, and how many payments match it in
+    /// total, ignoring its limit and offset
+    pub fn list_payments(&self, filter: &PaymentFilter) -> rusqlite::Result<(Vec<Payment>, u64)> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+
+        if let Some(user) = &filter.user {
+            conditions.push("username = ?");
+            values.push(Value::Text(user.clone()));
+        }
+
+        if let Some(status) = filter.status {
+            conditions.push("status = ?");
+            values.push(Value::Text(status.as_str().to_owned()));
+        }
+
+        if let Some(since) = filter.since {
+            conditions.push("created_at >= ?");
+            values.push(Value::Integer(since as i64));
+        }
+
+        if let Some(until) = filter.until {
+            conditions.push("created_at < ?");
+            values.push(Value::Integer(until as i64));
+        }
+
+        let condition = match conditions.is_empty() {
+            true => String::new(),
+            false => format!("WHERE {}", conditions.join(" AND ")),
+        };
+
+        let conn = self.conn();
+        let total = conn.query_row(
+            &format!("SELECT COUNT(*) FROM payments {condition}"),
+            rusqlite::params_from_iter(values.iter()),
+            |row| row.get(0),
+        )?;
+
+        let limit = filter
+            .limit
+            .unwrap_or(DEFAULT_PAYMENTS_LIMIT)
+            .min(MAX_PAYMENTS_LIMIT);
+        values.push(Value::Integer(limit as i64));
+        values.push(Value::Integer(filter.offset.unwrap_or(0) as i64));
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {PAYMENT_COLUMNS}
+             FROM payments {condition}
+             ORDER BY created_at DESC, payment_hash
+             LIMIT ? OFFSET ?"
+        ))?;
+        let payments = stmt
+            .query_map(rusqlite::params_from_iter(values.iter()), payment_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok((payments, total))
+    }
+}
+
+/// The columns [payment_from_row] expects, in order
+const PAYMENT_COLUMNS: &str =
+    "payment_hash, username, amount_msat, bolt11, zap_request, status, created_at, settled_at, \
+     comment, payer_data, preimage";
+
+fn payment_from_row(row: &Row) -> rusqlite::Result<Payment> {
+    let status: String = row.get(5)?;
+
+    Ok(Payment {
+        payment_hash: row.get(0)?,
+        username: row.get(1)?,
+        amount_msat: row.get(2)?,
+        bolt11: row.get(3)?,
+        zap_request: row.get(4)?,
+        status: PaymentStatus::parse(&status).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                5,
+                rusqlite::types::Type::Text,
+                format!("invalid payment status {status}").into(),
+            )
+        })?,
+        created_at: row.get(6)?,
+        settled_at: row.get(7)?,
+        comment: row.get(8)?,
+        payer_data: row.get(9)?,
+        preimage: row.get(10)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use secp256k1::Secp256k1;
+    use secp256k1::SecretKey;
+
+    use super::Database;
+    use super::Payment;
+    use super::PaymentFilter;
+    use super::PaymentStatus;
+    use crate::nostr::nostr_event::UnsignedEvent;
+    use crate::nostr::zap_handler::PendingZap;
+
+    fn pending_zap(payment_hash: &str) -> PendingZap {
+        let sec_key =
+            SecretKey::from_str("d7bee682d987439fae91bdc5fed8bbf16d84ec077a2bd5cf7592e384668198f3")
+                .unwrap();
+        let pubkey = sec_key.x_only_public_key(&Secp256k1::new()).0;
+
+        let event = UnsignedEvent {
+            pubkey: pubkey.to_string(),
+            created_at: 0,
+            kind: 9734,
+            tags: vec![vec!["relays".into(), "wss://relay.damus.io".into()]],
+            content: "".into(),
+        }
+        .into_signed(&sec_key);
+
+        PendingZap {
+            sender: pubkey,
+            receiver: pubkey,
+            bolt11: "lnbc1".into(),
+            payment_hash: payment_hash.into(),
+            description: serde_json::to_string(&event).unwrap(),
+            relays: PendingZap::relays_from_request(&event),
+            event,
+            lnurl: "https://example.com/.well-known/lnurlp/john".into(),
+            created_at: 42,
+        }
+    }
+
+    #[test]
+    fn test_inflight_zaps() {
+        let db = Database::open(":memory:").unwrap();
+        db.insert_inflight_zap(&pending_zap("aa")).unwrap();
+        db.insert_inflight_zap(&pending_zap("bb")).unwrap();
+        db.remove_inflight_zap("aa").unwrap();
+
+        let zaps = db.load_inflight_zaps().unwrap();
+        assert_eq!(zaps.len(), 1);
+        assert_eq!(zaps[0].payment_hash, "bb");
+        assert_eq!(zaps[0].created_at, 42);
+        assert_eq!(zaps[0].relays, vec!["wss://relay.damus.io".to_string()]);
+        assert_eq!(zaps[0].event.id, pending_zap("bb").event.id);
+    }
+
+    #[test]
+    fn test_zap_receipts() {
+        let db = Database::open(":memory:").unwrap();
+        db.insert_inflight_zap(&pending_zap("aa")).unwrap();
+
+        // the receipt takes the zap's place
+        let receipt = pending_zap("aa").event;
+        let relays = vec!["wss://nos.lol".to_string()];
+        db.save_zap_receipt("aa", &receipt, &relays).unwrap();
+        assert!(db.load_inflight_zaps().unwrap().is_empty());
+
+        let receipts = db.load_zap_receipts().unwrap();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].0.id, receipt.id);
+        assert_eq!(receipts[0].1, relays);
+
+        db.remove_zap_receipt(&receipt.id).unwrap();
+        assert!(db.load_zap_receipts().unwrap().is_empty());
+    }
+
+    fn payment(username: &str, payment_hash: &str, created_at: u64) -> Payment {
+        Payment {
+            username: username.into(),
+            amount_msat: 21_000,
+            payment_hash: payment_hash.into(),
+            bolt11: "lnbc1".into(),
+            zap_request: None,
+            comment: None,
+            payer_data: None,
+            status: PaymentStatus::Pending,
+            created_at,
+            settled_at: None,
+            preimage: None,
+        }
+    }
+
+    #[test]
+    fn test_payments() {
+        let db = Database::open(":memory:").unwrap();
+        db.insert_payment(&payment("john", "aa", 10)).unwrap();
+        db.insert_payment(&Payment {
+            comment: Some("thanks!".into()),
+            ..payment("john", "bb", 20)
+        })
+        .unwrap();
+        db.insert_payment(&payment("mary", "cc", 30)).unwrap();
+
+        assert_eq!(
+            db.get_payment("aa").unwrap(),
+            Some(payment("john", "aa", 10))
+        );
+        assert!(db.get_payment("dd").unwrap().is_none());
+
+        let settled = db.settle_payment("bb", 25, Some("ff")).unwrap().unwrap();
+        assert_eq!(settled.status, PaymentStatus::Settled);
+        assert_eq!(settled.comment.as_deref(), Some("thanks!"));
+        assert_eq!(settled.preimage.as_deref(), Some("ff"));
+        assert!(db.settle_payment("bb", 26, None).unwrap().is_none());
+        assert!(db.settle_payment("dd", 26, None).unwrap().is_none());
+
+        // preimages are only saved for settled payments
+        db.save_preimage("bb", "ee").unwrap();
+        db.save_preimage("cc", "ee").unwrap();
+        assert_eq!(
+            db.get_payment("bb").unwrap().unwrap().preimage.as_deref(),
+            Some("ee")
+        );
+        assert!(db.get_payment("cc").unwrap().unwrap().preimage.is_none());
+        db.expire_payments(15).unwrap();
+        assert_eq!(db.pending_payments().unwrap(), vec!["cc".to_string()]);
+
+        let (payments, total) = db.list_payments(&PaymentFilter::default()).unwrap();
+        assert_eq!(total, 3);
+        let hashes: Vec<_> = payments.iter().map(|p| p.payment_hash.as_str()).collect();
+        assert_eq!(hashes, vec!["cc", "bb", "aa"]);
+        assert_eq!(payments[1].status, PaymentStatus::Settled);
+        assert_eq!(payments[1].settled_at, Some(25));
+        assert_eq!(payments[2].status, PaymentStatus::Expired);
+
+        let filter = PaymentFilter {
+            user: Some("john".into()),
+            limit: Some(1),
+            offset: Some(1),
+            ..Default::default()
+        };
+        let (payments, total) = db.list_payments(&filter).unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(
+            payments,
+            vec![{
+                let mut aa = payment("john", "aa", 10);
+                aa.status = PaymentStatus::Expired;
+                aa
+            }]
+        );
+
+        let filter = PaymentFilter {
+            status: Some(PaymentStatus::Pending),
+            since: Some(30),
+            until: Some(31),
+            ..Default::default()
+        };
+        let (payments, _) = db.list_payments(&filter).unwrap();
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].username, "mary");
+    }
+}
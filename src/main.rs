@@ -2,27 +2,57 @@
 extern crate serde;
 
 mod api;
+mod backend;
+mod bech32;
 mod cli;
+mod db;
 mod nostr;
-mod phoenixd;
+mod users;
+
+use std::sync::Arc;
 
 use api::config::ServerConfig;
+use backend::cln::ClnClient;
+use backend::lnd::LndClient;
+use backend::phoenixd::PhoenixdClient;
+use backend::LightningBackend;
 use clap::Parser;
+use cli::Backend;
 use cli::Cli;
+use cli::UserStoreKind;
+use db::Database;
 use hex_conservative::DisplayHex;
+use nostr::nip19::parse_pubkey;
 use nostr::zap_handler::ZapHandler;
-use phoenixd::PhoenixdClient;
 use reqwest::Client;
 use secp256k1::Secp256k1;
+use users::dir::DirUserStore;
+use users::sqlite::SqliteUserStore;
+use users::UserStore;
+
+/// Relays we publish zap receipts to, if none are given to us
+const DEFAULT_RELAYS: [&str; 3] = ["wss://relay.damus.io", "wss://nos.lol", "wss://nostr.mom"];
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
     let cli = Cli::parse();
 
-    let ph_client = PhoenixdClient {
-        client: Client::default(),
-        password: cli.phoenixd_password,
-        host: cli.phoenixd_address.unwrap_or("127.0.0.1:9740".into()),
+    // clap makes sure we have all the arguments required by the selected backend
+    let backend: Arc<dyn LightningBackend> = match cli.backend.unwrap_or(Backend::Phoenixd) {
+        Backend::Phoenixd => Arc::new(PhoenixdClient {
+            client: Client::default(),
+            password: cli.phoenixd_password.expect("required by clap"),
+            host: cli.phoenixd_address.unwrap_or("127.0.0.1:9740".into()),
+        }),
+        Backend::Lnd => Arc::new(
+            LndClient::new(
+                cli.lnd_address.expect("required by clap"),
+                &cli.lnd_macaroon.expect("required by clap"),
+                &cli.lnd_cert.expect("required by clap"),
+            )
+            .map_err(|e| std::io::Error::other(e.to_string()))?,
+        ),
+        Backend::Cln => Arc::new(ClnClient::new(cli.cln_rpc_path.expect("required by clap"))),
     };
 
     let host = cli.api_host.unwrap_or("127.0.0.1".into());
@@ -35,15 +65,77 @@ async fn main() -> Result<(), std::io::Error> {
         .serialize()
         .to_lower_hex_string();
 
-    let (zap_handler, sender) = ZapHandler::new(ph_client.clone(), cli.secret_key).await;
+    let relays = match cli.relays.is_empty() {
+        true => DEFAULT_RELAYS
+            .iter()
+            .map(|relay| relay.to_string())
+            .collect(),
+        false => cli.relays,
+    };
+    let base_url = cli
+        .base_url
+        .or(cli
+            .domain
+            .as_ref()
+            .map(|domain| format!("https://{domain}")))
+        .map(|url| url.trim_end_matches('/').to_owned());
+
+    let db = Database::open(&cli.database.unwrap_or("./ln-address.db".into()))
+        .map_err(|e| std::io::Error::other(format!("can't open database: {e}")))?;
+
+    let admins = cli
+        .admins
+        .iter()
+        .map(|admin| {
+            parse_pubkey(admin)
+                .ok_or_else(|| std::io::Error::other(format!("invalid admin key {admin}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let min_sendable = cli.min_sendable.unwrap_or(1_000);
+    let max_sendable = cli.max_sendable.unwrap_or(10_000_000);
+    if min_sendable == 0 || min_sendable % 1000 != 0 {
+        return Err(std::io::Error::other(
+            "--min-sendable must be a positive multiple of 1000",
+        ));
+    }
+
+    if min_sendable > max_sendable {
+        return Err(std::io::Error::other(
+            "--min-sendable can't be bigger than --max-sendable",
+        ));
+    }
+
+    let users: Arc<dyn UserStore> = match cli.user_store.unwrap_or(UserStoreKind::Dir) {
+        UserStoreKind::Dir => {
+            let users = Arc::new(DirUserStore::new(
+                cli.users_dir.unwrap_or("./users".to_owned()),
+            ));
+            tokio::task::spawn(users.clone().watch());
+            users
+        }
+        UserStoreKind::Sqlite => Arc::new(SqliteUserStore::new(db.clone())),
+    };
+
+    let (zap_handler, sender) =
+        ZapHandler::new(backend.clone(), cli.secret_key, relays.clone(), db.clone());
 
     let _handler = tokio::task::spawn(zap_handler.run());
     let config = ServerConfig {
-        ph_client,
-        users_dir: cli.users_dir.unwrap_or("./users".to_owned()),
+        backend,
+        users,
         host: format!("{host}:{port}"),
         zap_sender: sender,
         zap_pk: pubkey,
+        secret_key: cli.secret_key,
+        base_url,
+        domain: cli.domain,
+        db,
+        relays,
+        min_sendable,
+        max_sendable,
+        admins,
+        seen_auth_events: Arc::default(),
     };
 
     api::api::run_server(config).await
@@ -1,3 +1,6 @@
-pub mod connection;
+pub mod nip19;
+pub mod nip98;
 pub mod nostr_event;
+pub mod relay_pool;
 pub mod zap_handler;
+pub mod zap_request;
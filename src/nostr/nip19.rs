@@ -0,0 +1,19 @@
+//! Bech32-encoded nostr entities, as described in NIP-19
+
+use std::str::FromStr;
+
+use secp256k1::XOnlyPublicKey;
+
+use crate::bech32;
+
+/// Parses a nostr public key, either hex-encoded or as an npub
+pub fn parse_pubkey(value: &str) -> Option<XOnlyPublicKey> {
+    if !value.starts_with("npub1") {
+        return XOnlyPublicKey::from_str(value).ok();
+    }
+
+    match bech32::decode(value) {
+        Ok((hrp, data)) if hrp == "npub" => XOnlyPublicKey::from_slice(&data).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,300 @@
+//! HTTP auth with nostr events (NIP-98)
+//!
+//! Clients sign a kind 27235 event for the url and method they are requesting, and send it
+//! base64-encoded in the Authorization header, as `Nostr <event>`.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use hex_conservative::DisplayHex;
+use secp256k1::XOnlyPublicKey;
+use sha2::Digest;
+use sha2::Sha256;
+
+use super::nip19::parse_pubkey;
+use super::nostr_event::Event;
+
+/// The kind for HTTP auth events
+pub const HTTP_AUTH_KIND: u16 = 27235;
+
+/// How far (in seconds) an auth event's created_at can be from our clock
+const MAX_TIME_DRIFT: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Why an auth event was rejected
+pub enum Nip98Error {
+    /// The Authorization header isn't a `Nostr` followed by a base64-encoded event
+    Malformed,
+    /// The event isn't a kind 27235
+    WrongKind,
+    /// Either the id or the signature don't match the event
+    InvalidSignature,
+    /// The event is too old, or from the future
+    Expired,
+    /// The u tag isn't the url being requested
+    WrongUrl,
+    /// The method tag isn't the method being used
+    WrongMethod,
+    /// The payload tag is missing, or isn't the sha256 of the request body
+    WrongPayload,
+    /// This event was already used for another request
+    Replayed,
+}
+
+impl Display for Nip98Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            Nip98Error::Malformed => "authorization should be a base64-encoded nostr event",
+            Nip98Error::WrongKind => "auth event should be a kind 27235",
+            Nip98Error::InvalidSignature => "invalid id or signature",
+            Nip98Error::Expired => "auth event is too old",
+            Nip98Error::WrongUrl => "u tag doesn't match the requested url",
+            Nip98Error::WrongMethod => "method tag doesn't match the request method",
+            Nip98Error::WrongPayload => "payload tag doesn't match the request body",
+            Nip98Error::Replayed => "auth event was already used",
+        };
+
+        write!(f, "{reason}")
+    }
+}
+
+/// Returns the value of the first tag with a given name
+fn tag<'a>(event: &'a Event, name: &str) -> Option<&'a str> {
+    event
+        .tags
+        .iter()
+        .find(|tag| tag.len() >= 2 && tag[0] == name)
+        .map(|tag| tag[1].as_str())
+}
+
+#[derive(Default)]
+/// The auth events we've accepted lately, so each of them can only be used once
+pub struct SeenEvents {
+    /// The ids of those events, and when they were created
+    events: Mutex<HashMap<String, u64>>,
+}
+
+impl SeenEvents {
+    /// Remembers an event, returning whether it's the first time we see it. Events are
+    /// forgotten once they'd be rejected as too old anyway
+    fn insert(&self, event: &Event, now: u64) -> bool {
+        let mut events = self.events.lock().expect("lock poisoned");
+        events.retain(|_, created_at| *created_at + MAX_TIME_DRIFT >= now);
+
+        events.insert(event.id.clone(), event.created_at).is_none()
+    }
+}
+
+/// Checks whether an Authorization header has a valid auth event for requesting `url` with
+/// `method` at `now`, returning who signed it
+///
+/// Requests with a `body` must have a payload tag with its sha256, otherwise a signed header
+/// could be replayed with another body. Every event can only be used once, as told by `seen`.
+pub fn validate_auth_header(
+    header: &str,
+    url: &str,
+    method: &str,
+    body: Option<&[u8]>,
+    now: u64,
+    seen: &SeenEvents,
+) -> Result<XOnlyPublicKey, Nip98Error> {
+    let event = header
+        .strip_prefix("Nostr ")
+        .and_then(|event| BASE64_STANDARD.decode(event.trim()).ok())
+        .and_then(|event| serde_json::from_slice::<Event>(&event).ok())
+        .ok_or(Nip98Error::Malformed)?;
+
+    if event.kind != HTTP_AUTH_KIND {
+        return Err(Nip98Error::WrongKind);
+    }
+
+    if !event.verify() {
+        return Err(Nip98Error::InvalidSignature);
+    }
+
+    if event.created_at.abs_diff(now) > MAX_TIME_DRIFT {
+        return Err(Nip98Error::Expired);
+    }
+
+    if tag(&event, "u").map(|u| u.trim_end_matches('/')) != Some(url.trim_end_matches('/')) {
+        return Err(Nip98Error::WrongUrl);
+    }
+
+    if !tag(&event, "method").is_some_and(|m| m.eq_ignore_ascii_case(method)) {
+        return Err(Nip98Error::WrongMethod);
+    }
+
+    if let Some(body) = body {
+        let payload = Sha256::digest(body).to_lower_hex_string();
+        if !tag(&event, "payload").is_some_and(|p| p.eq_ignore_ascii_case(&payload)) {
+            return Err(Nip98Error::WrongPayload);
+        }
+    }
+
+    let signer = parse_pubkey(&event.pubkey).ok_or(Nip98Error::InvalidSignature)?;
+
+    // only after everything else, so bogus requests can't use up a valid event
+    if !seen.insert(&event, now) {
+        return Err(Nip98Error::Replayed);
+    }
+
+    Ok(signer)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine;
+    use hex_conservative::DisplayHex;
+    use secp256k1::Secp256k1;
+    use secp256k1::SecretKey;
+    use sha2::Digest;
+    use sha2::Sha256;
+
+    use super::validate_auth_header;
+    use super::Nip98Error;
+    use super::SeenEvents;
+    use crate::nostr::nostr_event::UnsignedEvent;
+
+    const URL: &str = "https://smith.com/admin/payments?user=john";
+
+    fn auth_header(
+        kind: u16,
+        created_at: u64,
+        url: &str,
+        method: &str,
+        body: Option<&[u8]>,
+    ) -> String {
+        let sec_key =
+            SecretKey::from_str("d7bee682d987439fae91bdc5fed8bbf16d84ec077a2bd5cf7592e384668198f3")
+                .unwrap();
+
+        let mut tags = vec![
+            vec!["u".into(), url.into()],
+            vec!["method".into(), method.into()],
+        ];
+        if let Some(body) = body {
+            tags.push(vec![
+                "payload".into(),
+                Sha256::digest(body).to_lower_hex_string(),
+            ]);
+        }
+
+        let event = UnsignedEvent {
+            pubkey: sec_key.x_only_public_key(&Secp256k1::new()).0.to_string(),
+            created_at,
+            kind,
+            tags,
+            content: "".into(),
+        }
+        .into_signed(&sec_key);
+
+        format!(
+            "Nostr {}",
+            BASE64_STANDARD.encode(serde_json::to_string(&event).unwrap())
+        )
+    }
+
+    /// Validates a header for a request without a body, as if it was the first time we see it
+    fn validate(header: &str, url: &str, method: &str, now: u64) -> Result<(), Nip98Error> {
+        validate_auth_header(header, url, method, None, now, &SeenEvents::default()).map(|_| ())
+    }
+
+    #[test]
+    fn test_validate_auth_header() {
+        let header = auth_header(27235, 1_000, URL, "GET", None);
+        let signer =
+            validate_auth_header(&header, URL, "GET", None, 1_030, &SeenEvents::default()).unwrap();
+        let expected =
+            SecretKey::from_str("d7bee682d987439fae91bdc5fed8bbf16d84ec077a2bd5cf7592e384668198f3")
+                .unwrap()
+                .x_only_public_key(&Secp256k1::new())
+                .0;
+        assert_eq!(signer, expected);
+
+        assert_eq!(
+            validate(&header, URL, "GET", 1_100),
+            Err(Nip98Error::Expired)
+        );
+        assert_eq!(
+            validate(&header, URL, "DELETE", 1_000),
+            Err(Nip98Error::WrongMethod)
+        );
+        assert_eq!(
+            validate(&header, "https://smith.com/admin/payments", "GET", 1_000),
+            Err(Nip98Error::WrongUrl)
+        );
+        assert_eq!(
+            validate(&auth_header(1, 1_000, URL, "GET", None), URL, "GET", 1_000),
+            Err(Nip98Error::WrongKind)
+        );
+        assert_eq!(
+            validate(&header.replace("Nostr", "Bearer"), URL, "GET", 1_000),
+            Err(Nip98Error::Malformed)
+        );
+
+        // tamper with the event's content
+        let event = BASE64_STANDARD.decode(&header[6..]).unwrap();
+        let tampered = String::from_utf8(event)
+            .unwrap()
+            .replace("\"GET\"", "\"PUT\"");
+        let tampered = format!("Nostr {}", BASE64_STANDARD.encode(tampered));
+        assert_eq!(
+            validate(&tampered, URL, "PUT", 1_000),
+            Err(Nip98Error::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let body = br#"{"description": "my ln address"}"#;
+        let header = auth_header(27235, 1_000, URL, "POST", Some(body));
+        let check = |header: &str, body: &[u8]| {
+            validate_auth_header(
+                header,
+                URL,
+                "POST",
+                Some(body),
+                1_000,
+                &SeenEvents::default(),
+            )
+            .map(|_| ())
+        };
+
+        assert_eq!(check(&header, body), Ok(()));
+        assert_eq!(
+            check(&header, br#"{"description": "not mine"}"#),
+            Err(Nip98Error::WrongPayload)
+        );
+
+        // requests with a body must commit to it
+        let no_payload = auth_header(27235, 1_000, URL, "POST", None);
+        assert_eq!(check(&no_payload, body), Err(Nip98Error::WrongPayload));
+    }
+
+    #[test]
+    fn test_replay() {
+        let seen = SeenEvents::default();
+        let header = auth_header(27235, 1_000, URL, "GET", None);
+        let other = auth_header(27235, 1_001, URL, "GET", None);
+
+        assert!(validate_auth_header(&header, URL, "GET", None, 1_000, &seen).is_ok());
+        assert_eq!(
+            validate_auth_header(&header, URL, "GET", None, 1_030, &seen),
+            Err(Nip98Error::Replayed)
+        );
+        assert!(validate_auth_header(&other, URL, "GET", None, 1_030, &seen).is_ok());
+
+        // old events are forgotten, they'd be rejected as expired anyway
+        assert_eq!(
+            validate_auth_header(&other, URL, "GET", None, 1_061, &seen),
+            Err(Nip98Error::Replayed)
+        );
+        assert_eq!(seen.events.lock().unwrap().len(), 1);
+    }
+}
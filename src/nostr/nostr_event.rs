@@ -1,9 +1,12 @@
+use std::str::FromStr;
+
 use hex_conservative::DisplayHex;
 use secp256k1::schnorr::Signature;
 use secp256k1::Keypair;
 use secp256k1::Message;
 use secp256k1::Secp256k1;
 use secp256k1::SecretKey;
+use secp256k1::XOnlyPublicKey;
 use serde::Serialize;
 use serde_json::json;
 use sha2::Digest;
@@ -73,6 +76,32 @@ pub struct Event {
     pub sig: Signature,
 }
 
+impl Event {
+    /// Checks whether this event's id matches its content and is signed by its pubkey
+    pub fn verify(&self) -> bool {
+        let unsigned = UnsignedEvent {
+            pubkey: self.pubkey.clone(),
+            created_at: self.created_at,
+            kind: self.kind,
+            tags: self.tags.clone(),
+            content: self.content.clone(),
+        };
+
+        let id = unsigned.id();
+        if id.to_lower_hex_string() != self.id {
+            return false;
+        }
+
+        let Ok(pubkey) = XOnlyPublicKey::from_str(&self.pubkey) else {
+            return false;
+        };
+
+        Secp256k1::verification_only()
+            .verify_schnorr(&self.sig, &Message::from_digest(id), &pubkey)
+            .is_ok()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
@@ -0,0 +1,520 @@
+//! A pool of relay connections, used to publish our zap receipts
+//!
+//! Every relay runs in its own task, that keeps reconnecting with exponential backoff if the
+//! connection breaks. The pool keeps track of each relay's state and of which relays accepted
+//! each event we've published, so we can retry events no relay accepted.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures_util::SinkExt;
+use futures_util::StreamExt;
+use serde_json::json;
+use serde_json::Value;
+use tokio::select;
+use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+use tokio::time::sleep;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+use super::nostr_event::Event;
+
+/// How long we wait before the first reconnection attempt
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest we'll wait between reconnection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// How often we ping relays, so we notice dead connections
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long we wait for some relay to accept an event before publishing it again
+const RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// How many times we'll publish an event before giving up on it
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How long we keep track of a published event
+const PUBLICATION_EXPIRY: Duration = Duration::from_secs(3600);
+
+/// How long a relay we aren't configured to use may stay idle before we disconnect from it
+const IDLE_RELAY_EXPIRY: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The state of our connection with a relay
+pub enum RelayState {
+    /// We're trying to connect to this relay
+    Connecting,
+    /// We're connected and can send events to this relay
+    Connected,
+    /// We've lost our connection, and will try again later
+    Disconnected,
+}
+
+#[derive(Debug)]
+/// Something that happened to one of our relays
+pub enum RelayEvent {
+    /// Our connection with a relay changed
+    StateChanged { url: String, state: RelayState },
+    /// A relay answered an event we've published with an "OK" message
+    Ok {
+        url: String,
+        event_id: String,
+        accepted: bool,
+        message: String,
+    },
+}
+
+/// A relay in our pool
+struct Relay {
+    /// The current state of our connection with this relay
+    state: RelayState,
+    /// How many times in a row we've failed to connect to this relay
+    failures: u32,
+    /// Whether this relay is one of our configured relays, or one we've connected to because a
+    /// zap request asked us to. The latter are dropped after some time idle
+    persistent: bool,
+    /// The last time we've sent something to this relay
+    last_used: Instant,
+    /// Messages that should be sent to this relay
+    writer: Sender<String>,
+    /// The task running this relay's connection
+    task: JoinHandle<()>,
+}
+
+/// An event we've published, and who accepted it
+struct Publication {
+    /// The event itself, in case we need to send it again
+    event: Event,
+    /// Which relays this event should go to
+    relays: Vec<String>,
+    /// Which relays acknowledged this event with an "OK" true
+    accepted_by: HashSet<String>,
+    /// When we first published this event
+    created_at: Instant,
+    /// The last time we've sent this event
+    sent_at: Instant,
+    /// How many times we've sent this event
+    attempts: u32,
+}
+
+pub struct RelayPool {
+    /// Every relay we're connected to, or trying to connect to
+    relays: HashMap<String, Relay>,
+    /// The events we've published lately, indexed by their id
+    publications: HashMap<String, Publication>,
+    /// Given to every relay's task, so they can tell us what's happening
+    events_sender: Sender<RelayEvent>,
+    /// What's happening with our relays
+    events: Receiver<RelayEvent>,
+}
+
+impl RelayPool {
+    /// Creates a new pool, connecting to our configured relays
+    pub fn new(relays: &[String]) -> Self {
+        let (events_sender, events) = channel(1024);
+        let mut pool = Self {
+            relays: HashMap::new(),
+            publications: HashMap::new(),
+            events_sender,
+            events,
+        };
+
+        for url in relays {
+            pool.connect(url, true);
+        }
+
+        pool
+    }
+
+    /// Starts a connection to a relay, if we don't have one already
+    fn connect(&mut self, url: &str, persistent: bool) {
+        if let Some(relay) = self.relays.get_mut(url) {
+            relay.persistent |= persistent;
+            return;
+        }
+
+        let (writer, outgoing) = channel(1024);
+        let task = tokio::task::spawn(relay_loop(
+            url.to_owned(),
+            outgoing,
+            self.events_sender.clone(),
+        ));
+
+        self.relays.insert(
+            url.to_owned(),
+            Relay {
+                state: RelayState::Connecting,
+                failures: 0,
+                persistent,
+                last_used: Instant::now(),
+                writer,
+                task,
+            },
+        );
+    }
+
+    /// Sends a message to a relay. If we're disconnected, it'll be sent once we reconnect
+    fn send(&mut self, url: &str, message: String) {
+        let Some(relay) = self.relays.get_mut(url) else {
+            return;
+        };
+
+        relay.last_used = Instant::now();
+        if relay.writer.try_send(message).is_err() {
+            println!("relay {url} has too many queued messages, dropping one");
+        }
+    }
+
+    /// Publishes an event to our configured relays and to `extra_relays`
+    pub fn publish(&mut self, event: Event, extra_relays: &[String]) {
+        let mut relays: Vec<String> = self
+            .relays
+            .iter()
+            .filter(|(_, relay)| relay.persistent)
+            .map(|(url, _)| url.clone())
+            .collect();
+
+        for url in extra_relays {
+            if !relays.contains(url) {
+                self.connect(url, false);
+                relays.push(url.clone());
+            }
+        }
+
+        let connected = self
+            .relays
+            .values()
+            .any(|relay| relay.state == RelayState::Connected);
+        if !connected {
+            println!(
+                "no relay is connected, event {} will wait for a reconnection",
+                event.id
+            );
+        }
+
+        let message = json!(["EVENT", event]).to_string();
+        for url in relays.iter() {
+            self.send(url, message.clone());
+        }
+
+        self.publications.insert(
+            event.id.clone(),
+            Publication {
+                event,
+                relays,
+                accepted_by: HashSet::new(),
+                created_at: Instant::now(),
+                sent_at: Instant::now(),
+                attempts: 1,
+            },
+        );
+    }
+
+    /// Waits for something to happen with one of our relays
+    pub async fn recv(&mut self) -> Option<RelayEvent> {
+        self.events.recv().await
+    }
+
+    /// Updates our state with an event from one of our relays
+    ///
+    /// Returns the id of the event we've published, if this is the first relay accepting it
+    pub fn handle_event(&mut self, event: RelayEvent) -> Option<String> {
+        match event {
+            RelayEvent::StateChanged { url, state } => {
+                let relay = self.relays.get_mut(&url)?;
+
+                match state {
+                    RelayState::Connected => relay.failures = 0,
+                    RelayState::Disconnected => {
+                        relay.failures += 1;
+                        println!("disconnected from {url} ({} failures)", relay.failures);
+                    }
+                    RelayState::Connecting => {}
+                }
+                relay.state = state;
+                None
+            }
+
+            RelayEvent::Ok {
+                url,
+                event_id,
+                accepted,
+                message,
+            } => {
+                let publication = self.publications.get_mut(&event_id)?;
+
+                if !accepted {
+                    println!("{url} rejected event {event_id}: {message}");
+                    return None;
+                }
+
+                let first = publication.accepted_by.is_empty();
+                publication.accepted_by.insert(url);
+                first.then_some(event_id)
+            }
+        }
+    }
+
+    /// Publishes again every event that no relay accepted, and forgets about old events and idle
+    /// relays
+    pub fn retry_unacknowledged(&mut self) {
+        self.publications.retain(|id, publication| {
+            let expired = publication.created_at.elapsed() > PUBLICATION_EXPIRY;
+            let gave_up =
+                publication.accepted_by.is_empty() && publication.attempts >= MAX_ATTEMPTS;
+            if gave_up {
+                println!("no relay accepted event {id}, giving up");
+            }
+
+            !expired && !gave_up
+        });
+
+        let mut retries = Vec::new();
+        for publication in self.publications.values_mut() {
+            if !publication.accepted_by.is_empty() || publication.sent_at.elapsed() < RETRY_AFTER {
+                continue;
+            }
+
+            publication.attempts += 1;
+            publication.sent_at = Instant::now();
+            let message = json!(["EVENT", publication.event]).to_string();
+            retries.push((publication.relays.clone(), message));
+        }
+
+        for (relays, message) in retries {
+            for url in relays {
+                // we may have dropped this relay in the meantime
+                self.connect(&url, false);
+                self.send(&url, message.clone());
+            }
+        }
+
+        let pending: HashSet<&String> = self
+            .publications
+            .values()
+            .filter(|publication| publication.accepted_by.is_empty())
+            .flat_map(|publication| publication.relays.iter())
+            .collect();
+
+        self.relays.retain(|url, relay| {
+            let idle = !relay.persistent
+                && !pending.contains(url)
+                && relay.last_used.elapsed() > IDLE_RELAY_EXPIRY;
+            if idle {
+                relay.task.abort();
+            }
+
+            !idle
+        });
+    }
+}
+
+impl Drop for RelayPool {
+    fn drop(&mut self) {
+        for relay in self.relays.values() {
+            relay.task.abort();
+        }
+    }
+}
+
+/// Parses an ["OK", <event id>, <accepted>, <message>] message
+fn parse_ok(url: &str, text: &str) -> Option<RelayEvent> {
+    let message: Vec<Value> = serde_json::from_str(text).ok()?;
+    if message.first()?.as_str()? != "OK" {
+        return None;
+    }
+
+    Some(RelayEvent::Ok {
+        url: url.to_owned(),
+        event_id: message.get(1)?.as_str()?.to_owned(),
+        accepted: message.get(2)?.as_bool()?,
+        message: message
+            .get(3)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned(),
+    })
+}
+
+/// Keeps a connection with a relay, reconnecting if needed, until the pool is dropped
+async fn relay_loop(url: String, mut outgoing: Receiver<String>, events: Sender<RelayEvent>) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let state = RelayState::Connecting;
+        let _ = events
+            .send(RelayEvent::StateChanged {
+                url: url.clone(),
+                state,
+            })
+            .await;
+
+        if let Ok((ws_stream, _)) = connect_async(&url).await {
+            backoff = INITIAL_BACKOFF;
+            let state = RelayState::Connected;
+            let _ = events
+                .send(RelayEvent::StateChanged {
+                    url: url.clone(),
+                    state,
+                })
+                .await;
+
+            let (mut writer, mut reader) = ws_stream.split();
+            let mut ping = interval(PING_INTERVAL);
+
+            loop {
+                select! {
+                    message = outgoing.recv() => {
+                        // the pool is gone
+                        let Some(message) = message else {
+                            return;
+                        };
+
+                        if writer.send(Message::text(message)).await.is_err() {
+                            break;
+                        }
+                    }
+
+                    message = reader.next() => {
+                        match message {
+                            Some(Ok(Message::Text(text))) => {
+                                if let Some(event) = parse_ok(&url, &text) {
+                                    if events.send(event).await.is_err() {
+                                        return;
+                                    }
+                                }
+                            }
+                            Some(Ok(Message::Ping(data))) => {
+                                if writer.send(Message::Pong(data)).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                            Some(Ok(_)) => {}
+                        }
+                    }
+
+                    _ = ping.tick() => {
+                        if writer.send(Message::Ping(Vec::new())).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        let state = RelayState::Disconnected;
+        let event = RelayEvent::StateChanged {
+            url: url.clone(),
+            state,
+        };
+        if events.send(event).await.is_err() {
+            return;
+        }
+
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use futures_util::SinkExt;
+    use futures_util::StreamExt;
+    use secp256k1::SecretKey;
+    use serde_json::json;
+    use serde_json::Value;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::parse_ok;
+    use super::RelayEvent;
+    use super::RelayPool;
+    use super::RelayState;
+    use crate::nostr::nostr_event::UnsignedEvent;
+
+    /// Starts a relay that accepts every event
+    async fn fake_relay() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        tokio::task::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut ws_stream = accept_async(stream).await.unwrap();
+                while let Some(Ok(Message::Text(text))) = ws_stream.next().await {
+                    let message: Vec<Value> = serde_json::from_str(&text).unwrap();
+                    let ok = json!(["OK", message[1]["id"], true, ""]).to_string();
+                    ws_stream.send(Message::text(ok)).await.unwrap();
+                }
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn test_publish() {
+        let url = fake_relay().await;
+        let mut pool = RelayPool::new(std::slice::from_ref(&url));
+
+        let sec_key =
+            SecretKey::from_str("d7bee682d987439fae91bdc5fed8bbf16d84ec077a2bd5cf7592e384668198f3")
+                .unwrap();
+        let event = UnsignedEvent {
+            pubkey: sec_key
+                .x_only_public_key(&secp256k1::Secp256k1::new())
+                .0
+                .to_string(),
+            created_at: 0,
+            kind: 9735,
+            tags: Vec::new(),
+            content: "".into(),
+        }
+        .into_signed(&sec_key);
+        let id = event.id.clone();
+        pool.publish(event, &[]);
+
+        let accepted = loop {
+            let event = pool.recv().await.unwrap();
+            if let Some(accepted) = pool.handle_event(event) {
+                break accepted;
+            }
+        };
+
+        assert_eq!(accepted, id);
+
+        assert!(pool.publications[&id].accepted_by.contains(&url));
+        assert_eq!(pool.relays[&url].state, RelayState::Connected);
+    }
+
+    #[test]
+    fn test_parse_ok() {
+        let Some(RelayEvent::Ok {
+            url,
+            event_id,
+            accepted,
+            message,
+        }) = parse_ok("wss://relay", r#"["OK", "abcd", false, "blocked: spam"]"#)
+        else {
+            panic!("should parse an OK message");
+        };
+
+        assert_eq!(url, "wss://relay");
+        assert_eq!(event_id, "abcd");
+        assert!(!accepted);
+        assert_eq!(message, "blocked: spam");
+
+        assert!(parse_ok("wss://relay", r#"["NOTICE", "hello"]"#).is_none());
+        assert!(parse_ok("wss://relay", r#"["OK", "abcd"]"#).is_none());
+    }
+}
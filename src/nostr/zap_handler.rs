@@ -1,26 +1,32 @@
+use std::future::pending;
+use std::sync::Arc;
 use std::time::Duration;
-use std::time::Instant;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 use hex_conservative::DisplayHex;
-use reqwest::Client;
 use secp256k1::Secp256k1;
 use secp256k1::SecretKey;
 use secp256k1::XOnlyPublicKey;
-use serde_json::json;
+use tokio::select;
 use tokio::sync::mpsc::channel;
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::Sender;
-use tokio::time::timeout;
-use tokio_tungstenite::tungstenite::Message;
+use tokio::time::interval;
+use tokio::time::sleep;
 
-use super::connection::WebsocketConnection;
 use super::nostr_event::Event;
+use super::relay_pool::RelayPool;
+use crate::backend::IncomingPayment;
+use crate::backend::LightningBackend;
+use crate::backend::INVOICE_EXPIRY;
+use crate::db::Database;
 use crate::nostr::nostr_event::UnsignedEvent;
-use crate::phoenixd::PhoenixdClient;
 
 /// The context for our zap handler.
+///
+/// Besides zaps, this is also what watches our backend for payments, keeping the payment ledger
+/// up to date.
 pub struct ZapHandler {
     /// Every zap requires an event called "zap receipt", that should be published by the lnaddress
     /// server, after it see the payment. As a nostr note, it needs a pubkey and signature to work.
@@ -31,19 +37,14 @@ pub struct ZapHandler {
     public_key_str: String,
     /// This channel weill receive the zap requests from the http server
     receiver: Receiver<PendingZap>,
-    /// Our phoenixd we'll use to probe the invoice
-    phoenixd: PhoenixdClient,
-    /// A list of connected relays that we'll use to send the zap receipts
-    connected_relays: Vec<WebsocketConnection>,
-    /// A channel with messages from our connected relays
-    relays_receiver: Receiver<(usize, Message)>,
-    /// The sender we give to relays to connect with us, we keep it here for every time we create a
-    /// new connection
-    relays_sender: Sender<(usize, Message)>,
+    /// The lightning backend we'll use to probe the invoice
+    backend: Arc<dyn LightningBackend>,
+    /// The relays we use to publish zap receipts
+    relay_pool: RelayPool,
     /// Inflight zaps that haven't being paid yet
-    inflight: Vec<(PendingZap, Instant)>,
-    /// The id of the latest connection we've created
-    ids: usize,
+    inflight: Vec<PendingZap>,
+    /// Where we keep a copy of our inflight zaps, so they survive restarts, and our payment ledger
+    db: Database,
 }
 
 /// A zap that was requested but haven't being paid yet
@@ -58,59 +59,103 @@ pub struct PendingZap {
     pub payment_hash: String,
     /// The zap request event
     pub event: Event,
+    /// The zap request exactly as we've received it. The invoice commits to its hash, so the
+    /// receipt must carry this exact string
+    pub description: String,
+    /// The relays from the zap request's relays tag, where the receipt should be published
+    pub relays: Vec<String>,
+    /// The lnurlp url for the zapped user
+    pub lnurl: String,
+    /// When this zap was requested, as a unix timestamp
+    pub created_at: u64,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct IncomingPaymentInfo {
-    #[serde(rename = "paymentHash")]
-    payment_hash: String,
-    preimage: String,
-    description: String,
-    invoice: String,
-    #[serde(rename = "isPaid")]
-    is_paid: bool,
-    #[serde(rename = "receivedSat")]
-    received_sat: u64,
-    fees: u64,
-    #[serde(rename = "createdAt")]
-    created_at: u64,
+impl PendingZap {
+    /// Returns the relays listed in a zap request's relays tag
+    ///
+    /// We only take the first few valid websocket urls, since each one of them may need a new
+    /// connection.
+    pub fn relays_from_request(event: &Event) -> Vec<String> {
+        let mut relays = Vec::new();
+        let tag = event
+            .tags
+            .iter()
+            .find(|tag| tag.first().map(String::as_str) == Some("relays"));
+
+        for relay in tag.into_iter().flatten().skip(1) {
+            let relay = relay.trim_end_matches('/').to_owned();
+            let is_websocket = relay.starts_with("wss://") || relay.starts_with("ws://");
+            if is_websocket && !relays.contains(&relay) {
+                relays.push(relay);
+            }
+
+            if relays.len() == MAX_ZAP_RELAYS {
+                break;
+            }
+        }
+
+        relays
+    }
 }
 
-// for now, just use a hard-coded list of relays
-const RELAYS: [&str; 4] = [
-    "wss://relay.damus.io",
-    "wss://nos.lol",
-    "wss://nostr.mom",
-    "wss://nostr.dlsouza.lol",
-];
+/// How many relays from a zap request we'll publish its receipt to
+const MAX_ZAP_RELAYS: usize = 10;
+
+/// How long we keep trying to publish a zap receipt across restarts, in seconds
+const RECEIPT_EXPIRY: u64 = 86400;
+
+/// Returns the current unix timestamp
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// How long we wait before trying to subscribe to our backend again, if the subscription breaks
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(5);
 
 impl ZapHandler {
-    pub async fn new(
-        phoenixd: PhoenixdClient,
+    pub fn new(
+        backend: Arc<dyn LightningBackend>,
         secret_key: SecretKey,
+        relays: Vec<String>,
+        db: Database,
     ) -> (Self, Sender<PendingZap>) {
         let (sender, receiver) = channel(1024);
-        let (ws_send, ws_recv) = channel(1024);
-
-        let mut connected_relays = vec![];
-        let mut ids = 0;
-        for relay_url in RELAYS {
-            let relay = WebsocketConnection::new(ids, relay_url.into(), ws_send.clone()).await;
-            if let Ok(relay) = relay {
-                connected_relays.push(relay);
+
+        // zaps requested before a restart. The ones paid while we were down get their receipts
+        // once we subscribe to our backend
+        let inflight = db.load_inflight_zaps().unwrap_or_else(|e| {
+            println!("can't load inflight zaps: {e}");
+            Vec::new()
+        });
+
+        // receipts no relay accepted before a restart
+        let mut relay_pool = RelayPool::new(&relays);
+        let receipts = db.load_zap_receipts().unwrap_or_else(|e| {
+            println!("can't load zap receipts: {e}");
+            Vec::new()
+        });
+        for (receipt, relays) in receipts {
+            if now().saturating_sub(receipt.created_at) >= RECEIPT_EXPIRY {
+                println!("no relay accepted zap receipt {}, giving up", receipt.id);
+                if let Err(e) = db.remove_zap_receipt(&receipt.id) {
+                    println!("can't remove zap receipt {} from database: {e}", receipt.id);
+                }
+                continue;
             }
-            ids += 1;
+
+            relay_pool.publish(receipt, &relays);
         }
 
         (
             Self {
-                ids,
-                relays_sender: ws_send,
-                relays_receiver: ws_recv,
-                connected_relays,
-                phoenixd,
+                relay_pool,
+                backend,
                 receiver,
-                inflight: Vec::new(),
+                inflight,
+                db,
                 public_key_str: secret_key
                     .x_only_public_key(&Secp256k1::new())
                     .0
@@ -122,143 +167,385 @@ impl ZapHandler {
         )
     }
 
-    async fn check_pending_zaps(&mut self) -> reqwest::Result<()> {
-        // remove old zap receipts
-        self.inflight.retain(|zap| zap.1.elapsed().as_secs() < 3600);
-        let client = Client::default();
-        let mut paid_or_expired = Vec::new();
-
-        for (event, when) in self.inflight.iter() {
-            let res = client
-                .get(format!(
-                    "http://{}/payments/incoming/{}",
-                    self.phoenixd.host, event.payment_hash
-                ))
-                .basic_auth("".to_string(), Some(&self.phoenixd.password))
-                .send()
-                .await?
-                .text()
-                .await?;
-
-            let res: IncomingPaymentInfo =
-                ::serde_json::from_str(&res).expect("Phoenixd is doing something funky");
-            if when.elapsed().as_secs() > 3600 {
-                paid_or_expired.push(event.payment_hash.clone());
-            }
-
-            if !res.is_paid {
-                continue;
+    /// Asks the backend about every pending payment and inflight zap, handling the ones that got
+    /// paid
+    ///
+    /// We only need this after (re)subscribing to the backend, since payments received while we
+    /// weren't listening never show up in the subscription. A payment we can't look up (e.g. the
+    /// backend forgot about it) is logged and skipped, so it can't hold back every other one.
+    async fn check_pending_payments(&mut self) {
+        let mut pending = self.db.pending_payments().unwrap_or_else(|e| {
+            println!("can't load pending payments: {e}");
+            Vec::new()
+        });
+        for zap in self.inflight.iter() {
+            if !pending.contains(&zap.payment_hash) {
+                pending.push(zap.payment_hash.clone());
             }
+        }
 
-            paid_or_expired.push(event.payment_hash.clone());
-
-            let payee = event.receiver.serialize().to_lower_hex_string();
-            let payer = &event.event.pubkey;
-            let e_tag = event
-                .event
-                .tags
-                .iter()
-                .position(|tag| tag[0] == "e".to_owned());
-
-            let mut zap_receipt = UnsignedEvent {
-                content: "".to_string(),
-                created_at: SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs(),
-                kind: 9735,
-                pubkey: self.public_key_str.clone(),
-                tags: vec![
-                    vec!["p".to_string(), payee],
-                    vec!["P".to_string(), payer.to_owned()],
-                    vec!["relays".to_string(), "wss://nostr.dlsouza.lol".to_string()],
-                    vec![
-                        "lnurl".to_string(),
-                        "https://dlsouza.lol/callback".to_string(),
-                    ],
-                    vec!["amount".to_string(), res.received_sat.to_string()],
-                    vec![
-                        "description".to_string(),
-                        ::serde_json::to_string(&event.event).unwrap(),
-                    ],
-                    vec!["bolt11".to_string(), event.bolt11.clone()],
-                ],
+        let mut paid = Vec::new();
+        for payment_hash in pending {
+            let res = match self.backend.lookup_incoming_payment(&payment_hash).await {
+                Ok(res) => res,
+                Err(e) => {
+                    println!("can't look up payment {payment_hash}: {e}");
+                    continue;
+                }
             };
 
-            if e_tag.is_some() {
-                zap_receipt
-                    .tags
-                    .push(event.event.tags[e_tag.unwrap()].clone());
+            if res.is_paid {
+                paid.push(res);
             }
+        }
+
+        for payment in paid {
+            self.handle_payment(payment);
+        }
 
-            let zap_receipt = zap_receipt.into_signed(&self.secret_key);
+        // only expire invoices after checking them, some may have been paid while we were down
+        self.remove_expired_invoices();
+    }
 
-            let text_zap = json!(["EVENT", zap_receipt]).to_string();
-            let ws_msg = Message::text(text_zap);
-            for relay in self.connected_relays.iter_mut() {
-                if let Err(e) = relay.write_to_connection(ws_msg.clone()).await {
-                    println!("{e:?}");
-                }
-            }
+    /// Called every time the backend tells us about a payment. Settles it in our ledger and, if
+    /// it's for one of our zaps, publishes the zap receipt
+    fn handle_payment(&mut self, payment: IncomingPayment) {
+        let settled =
+            self.db
+                .settle_payment(&payment.payment_hash, now(), payment.preimage.as_deref());
+        match settled {
+            Ok(Some(settled)) => match settled.comment {
+                Some(comment) => println!(
+                    "{} received {} msat: {comment}",
+                    settled.username, payment.received_msat
+                ),
+                None => println!(
+                    "{} received {} msat",
+                    settled.username, payment.received_msat
+                ),
+            },
+            Ok(None) => {}
+            Err(e) => println!("can't settle payment {}: {e}", payment.payment_hash),
+        }
+
+        let Some(position) = self
+            .inflight
+            .iter()
+            .position(|zap| zap.payment_hash == payment.payment_hash)
+        else {
+            return;
+        };
+
+        let zap = self.inflight.remove(position);
+        self.publish_receipt(&zap, &payment);
+    }
+
+    /// Signs and publishes the receipt for a paid zap. It replaces the zap in our database until
+    /// some relay accepts it, so it isn't lost if we restart before that
+    fn publish_receipt(&mut self, zap: &PendingZap, payment: &IncomingPayment) {
+        let payee = zap.receiver.serialize().to_lower_hex_string();
+        let payer = zap.sender.serialize().to_lower_hex_string();
+        let e_tag = zap
+            .event
+            .tags
+            .iter()
+            .find(|tag| tag.first().map(String::as_str) == Some("e"));
+
+        let mut zap_receipt = UnsignedEvent {
+            content: "".to_string(),
+            created_at: now(),
+            kind: 9735,
+            pubkey: self.public_key_str.clone(),
+            tags: vec![
+                vec!["p".to_string(), payee],
+                vec!["P".to_string(), payer],
+                vec!["lnurl".to_string(), zap.lnurl.clone()],
+                vec!["amount".to_string(), payment.received_msat.to_string()],
+                vec!["description".to_string(), zap.description.clone()],
+                vec!["bolt11".to_string(), zap.bolt11.clone()],
+            ],
+        };
+
+        if let Some(e_tag) = e_tag {
+            zap_receipt.tags.push(e_tag.clone());
+        }
+
+        let zap_receipt = zap_receipt.into_signed(&self.secret_key);
+        if let Err(e) = self
+            .db
+            .save_zap_receipt(&zap.payment_hash, &zap_receipt, &zap.relays)
+        {
+            println!("can't save zap receipt {} to database: {e}", zap_receipt.id);
         }
 
-        self.inflight
-            .retain(|event| !paid_or_expired.contains(&event.0.payment_hash));
+        // NIP-57 says receipts go to the relays in the zap request, but we also send them to
+        // our own relays
+        self.relay_pool.publish(zap_receipt, &zap.relays);
+    }
 
-        Ok(())
+    /// Removes a zap from our database
+    fn forget_zap(&self, zap: &PendingZap) {
+        if let Err(e) = self.db.remove_inflight_zap(&zap.payment_hash) {
+            println!("can't remove zap {} from database: {e}", zap.payment_hash);
+        }
+    }
+
+    /// Removes a zap receipt from our database, once some relay accepted it
+    fn forget_receipt(&self, event_id: &str) {
+        if let Err(e) = self.db.remove_zap_receipt(event_id) {
+            println!("can't remove zap receipt {event_id} from database: {e}");
+        }
+    }
+
+    /// Forgets about invoices and zaps that weren't paid after one hour
+    fn remove_expired_invoices(&mut self) {
+        let now = now();
+        let (expired, inflight) = std::mem::take(&mut self.inflight)
+            .into_iter()
+            .partition(|zap| now.saturating_sub(zap.created_at) >= INVOICE_EXPIRY);
+        self.inflight = inflight;
+
+        for zap in expired {
+            self.forget_zap(&zap);
+        }
+
+        if let Err(e) = self.db.expire_payments(now.saturating_sub(INVOICE_EXPIRY)) {
+            println!("can't expire payments: {e}");
+        }
     }
 
     pub fn remove_older_zap(&mut self) {
-        self.inflight.sort_by(|first, second| {
-            first
-                .1
-                .elapsed()
-                .as_secs()
-                .cmp(&second.1.elapsed().as_secs())
-        });
-        self.inflight.pop();
+        let Some(oldest) = self
+            .inflight
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, zap)| zap.created_at)
+            .map(|(position, _)| position)
+        else {
+            return;
+        };
+
+        let zap = self.inflight.remove(oldest);
+        self.forget_zap(&zap);
+    }
+
+    /// Subscribes to payments received by our backend, and then looks for any payment we got
+    /// while we weren't subscribed
+    async fn subscribe(&mut self) -> Option<Receiver<IncomingPayment>> {
+        let payments = match self.backend.subscribe_payments().await {
+            Ok(payments) => payments,
+            Err(e) => {
+                println!("can't subscribe to payments: {e}");
+                return None;
+            }
+        };
+
+        self.check_pending_payments().await;
+
+        Some(payments)
     }
 
     pub async fn run(mut self) {
+        let mut payments = self.subscribe().await;
+        let mut cleanup = interval(Duration::from_secs(30));
+
         loop {
-            if let Err(e) = self.check_pending_zaps().await {
-                println!("{e:?}");
-            }
+            select! {
+                zap = self.receiver.recv() => {
+                    // the http server is gone, nothing else to do
+                    let Some(zap) = zap else {
+                        return;
+                    };
+
+                    if self.inflight.len() > 1_000 {
+                        self.remove_older_zap();
+                    }
+
+                    if let Err(e) = self.db.insert_inflight_zap(&zap) {
+                        println!("can't save zap {} to database: {e}", zap.payment_hash);
+                    }
+                    self.inflight.push(zap);
+                }
 
-            if let Ok(msg) = self.relays_receiver.try_recv() {
-                match msg.1 {
-                    Message::Close(_) => {
-                        let relay = self
-                            .connected_relays
-                            .iter()
-                            .position(|r| r.id() == msg.0)
-                            .expect("got disconnect from a relay not connected?");
-                        let relay = self.connected_relays.remove(relay);
-
-                        let relay = WebsocketConnection::new(
-                            self.ids,
-                            relay.address(),
-                            self.relays_sender.clone(),
-                        )
-                        .await;
-
-                        if let Ok(relay) = relay {
-                            self.connected_relays.push(relay);
+                payment = next_payment(&mut payments) => {
+                    match payment {
+                        Some(payment) => self.handle_payment(payment),
+                        None => {
+                            println!("lost our payment subscription, reconnecting");
+                            payments = None;
                         }
                     }
+                }
+
+                _ = sleep(RESUBSCRIBE_INTERVAL), if payments.is_none() => {
+                    payments = self.subscribe().await;
+                }
 
-                    _ => {}
+                Some(event) = self.relay_pool.recv() => {
+                    if let Some(event_id) = self.relay_pool.handle_event(event) {
+                        self.forget_receipt(&event_id);
+                    }
+                }
+
+                _ = cleanup.tick() => {
+                    self.remove_expired_invoices();
+                    self.relay_pool.retry_unacknowledged();
                 }
             }
+        }
+    }
+}
 
-            let Ok(Some(event)) = timeout(Duration::from_secs(1), self.receiver.recv()).await
-            else {
-                continue;
-            };
-            if self.inflight.len() > 1_000 {
-                self.remove_older_zap();
+/// Waits for the next payment in our subscription, or forever if we don't have one
+async fn next_payment(payments: &mut Option<Receiver<IncomingPayment>>) -> Option<IncomingPayment> {
+    match payments {
+        Some(payments) => payments.recv().await,
+        None => pending().await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use secp256k1::Secp256k1;
+    use secp256k1::SecretKey;
+    use tokio::sync::mpsc::Receiver;
+
+    use super::now;
+    use super::PendingZap;
+    use super::ZapHandler;
+    use crate::backend::BackendError;
+    use crate::backend::IncomingPayment;
+    use crate::backend::Invoice;
+    use crate::backend::InvoiceRequest;
+    use crate::backend::LightningBackend;
+    use crate::backend::INVOICE_EXPIRY;
+    use crate::db::Database;
+    use crate::db::Payment;
+    use crate::db::PaymentStatus;
+    use crate::nostr::nostr_event::UnsignedEvent;
+
+    /// A backend that forgot about "aa", and says everything else was paid. It can only look up
+    /// payments
+    struct ForgetfulBackend;
+
+    #[async_trait]
+    impl LightningBackend for ForgetfulBackend {
+        async fn create_invoice(&self, _: InvoiceRequest) -> Result<Invoice, BackendError> {
+            Err(BackendError::Connection("can't create invoices".into()))
+        }
+
+        async fn lookup_incoming_payment(
+            &self,
+            payment_hash: &str,
+        ) -> Result<IncomingPayment, BackendError> {
+            if payment_hash == "aa" {
+                return Err(BackendError::InvalidResponse("unknown invoice".into()));
             }
-            self.inflight.push((event, Instant::now()));
+
+            Ok(IncomingPayment {
+                payment_hash: payment_hash.into(),
+                is_paid: true,
+                received_msat: 21_000,
+                preimage: None,
+            })
+        }
+
+        async fn subscribe_payments(&self) -> Result<Receiver<IncomingPayment>, BackendError> {
+            Err(BackendError::Connection("can't subscribe".into()))
+        }
+    }
+
+    fn payment(payment_hash: &str, created_at: u64) -> Payment {
+        Payment {
+            username: "john".into(),
+            amount_msat: 21_000,
+            payment_hash: payment_hash.into(),
+            bolt11: "lnbc1".into(),
+            zap_request: None,
+            comment: None,
+            payer_data: None,
+            status: PaymentStatus::Pending,
+            created_at,
+            settled_at: None,
+            preimage: None,
         }
     }
+
+    #[tokio::test]
+    async fn test_check_pending_payments() {
+        let db = Database::open(":memory:").unwrap();
+        let old = now() - INVOICE_EXPIRY - 1;
+        db.insert_payment(&payment("aa", old)).unwrap();
+        db.insert_payment(&payment("bb", old + 1)).unwrap();
+
+        let secret_key =
+            SecretKey::from_str("d7bee682d987439fae91bdc5fed8bbf16d84ec077a2bd5cf7592e384668198f3")
+                .unwrap();
+        let (mut handler, _sender) = ZapHandler::new(
+            Arc::new(ForgetfulBackend),
+            secret_key,
+            Vec::new(),
+            db.clone(),
+        );
+        handler.check_pending_payments().await;
+
+        // failing to look up "aa" doesn't stop us from settling "bb", nor from expiring "aa"
+        let status = |hash| db.get_payment(hash).unwrap().unwrap().status;
+        assert_eq!(status("aa"), PaymentStatus::Expired);
+        assert_eq!(status("bb"), PaymentStatus::Settled);
+    }
+
+    #[tokio::test]
+    async fn test_receipt_with_empty_tag() {
+        let db = Database::open(":memory:").unwrap();
+        db.insert_payment(&payment("bb", now())).unwrap();
+
+        let secret_key =
+            SecretKey::from_str("d7bee682d987439fae91bdc5fed8bbf16d84ec077a2bd5cf7592e384668198f3")
+                .unwrap();
+        let (mut handler, _sender) = ZapHandler::new(
+            Arc::new(ForgetfulBackend),
+            secret_key,
+            Vec::new(),
+            db.clone(),
+        );
+
+        // zap requests with empty tags are rejected now, but older ones may still be in our
+        // database
+        let pubkey = secret_key.x_only_public_key(&Secp256k1::new()).0;
+        let event = UnsignedEvent {
+            pubkey: pubkey.to_string(),
+            created_at: now(),
+            kind: 9734,
+            tags: vec![vec![], vec!["e".into(), "ee".into()]],
+            content: "".into(),
+        }
+        .into_signed(&secret_key);
+        handler.inflight.push(PendingZap {
+            sender: pubkey,
+            receiver: pubkey,
+            bolt11: "lnbc1".into(),
+            payment_hash: "bb".into(),
+            event,
+            description: "{}".into(),
+            relays: Vec::new(),
+            lnurl: "lnurl1".into(),
+            created_at: now(),
+        });
+
+        handler.handle_payment(IncomingPayment {
+            payment_hash: "bb".into(),
+            is_paid: true,
+            received_msat: 21_000,
+            preimage: None,
+        });
+
+        let receipts = db.load_zap_receipts().unwrap();
+        assert_eq!(receipts.len(), 1);
+        assert!(receipts[0].0.tags.contains(&vec!["e".into(), "ee".into()]));
+    }
 }
@@ -0,0 +1,207 @@
+//! Validation for zap requests (kind 9734), following NIP-57's Appendix D
+
+use std::fmt::Display;
+
+use hex_conservative::DisplayHex;
+use secp256k1::XOnlyPublicKey;
+
+use super::nostr_event::Event;
+
+/// The kind for zap requests
+pub const ZAP_REQUEST_KIND: u16 = 9734;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Why a zap request was rejected
+pub enum ZapRequestError {
+    /// This user can't receive zaps, because they don't have a nostr key
+    ZapsNotAllowed,
+    /// The zap request isn't a valid json-encoded nostr event
+    Malformed,
+    /// The event isn't a kind 9734
+    WrongKind,
+    /// Either the id or the signature don't match the event
+    InvalidSignature,
+    /// One of the tags is empty, so it doesn't even have a name
+    EmptyTag,
+    /// The zap request should have exactly one p tag
+    InvalidRecipientTag,
+    /// The p tag isn't the user being paid
+    WrongRecipient,
+    /// The zap request has more than one e tag
+    MultipleEventTags,
+    /// The amount tag isn't the amount being paid
+    AmountMismatch,
+    /// There's no relays tag, so we don't know where to publish the receipt
+    MissingRelays,
+}
+
+impl Display for ZapRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            ZapRequestError::ZapsNotAllowed => "this user doesn't accept zaps",
+            ZapRequestError::Malformed => "zap request isn't a valid nostr event",
+            ZapRequestError::WrongKind => "zap request should be a kind 9734",
+            ZapRequestError::InvalidSignature => "invalid id or signature",
+            ZapRequestError::EmptyTag => "zap request can't have empty tags",
+            ZapRequestError::InvalidRecipientTag => "zap request must have exactly one p tag",
+            ZapRequestError::WrongRecipient => "p tag doesn't match the recipient",
+            ZapRequestError::MultipleEventTags => "zap request can't have more than one e tag",
+            ZapRequestError::AmountMismatch => "amount tag doesn't match the requested amount",
+            ZapRequestError::MissingRelays => "zap request must have a relays tag",
+        };
+
+        write!(f, "{reason}")
+    }
+}
+
+/// Returns every tag with a given name that has a value
+fn tags<'a>(event: &'a Event, name: &'a str) -> impl Iterator<Item = &'a Vec<String>> {
+    event
+        .tags
+        .iter()
+        .filter(move |tag| tag.len() >= 2 && tag[0] == name)
+}
+
+/// Checks whether `event` is a valid zap request for paying `amount_msat` to `recipient`,
+/// returning who's zapping
+pub fn validate_zap_request(
+    event: &Event,
+    recipient: &XOnlyPublicKey,
+    amount_msat: u64,
+) -> Result<XOnlyPublicKey, ZapRequestError> {
+    if event.kind != ZAP_REQUEST_KIND {
+        return Err(ZapRequestError::WrongKind);
+    }
+
+    if !event.verify() {
+        return Err(ZapRequestError::InvalidSignature);
+    }
+
+    if event.tags.iter().any(Vec::is_empty) {
+        return Err(ZapRequestError::EmptyTag);
+    }
+
+    let p_tags: Vec<_> = tags(event, "p").collect();
+    let [p_tag] = p_tags[..] else {
+        return Err(ZapRequestError::InvalidRecipientTag);
+    };
+
+    if p_tag[1] != recipient.serialize().to_lower_hex_string() {
+        return Err(ZapRequestError::WrongRecipient);
+    }
+
+    if tags(event, "e").count() > 1 {
+        return Err(ZapRequestError::MultipleEventTags);
+    }
+
+    if let Some(amount) = tags(event, "amount").next() {
+        if amount[1].parse::<u64>() != Ok(amount_msat) {
+            return Err(ZapRequestError::AmountMismatch);
+        }
+    }
+
+    if tags(event, "relays").next().is_none() {
+        return Err(ZapRequestError::MissingRelays);
+    }
+
+    event
+        .pubkey
+        .parse()
+        .map_err(|_| ZapRequestError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use secp256k1::Secp256k1;
+    use secp256k1::SecretKey;
+    use secp256k1::XOnlyPublicKey;
+
+    use super::validate_zap_request;
+    use super::ZapRequestError;
+    use crate::nostr::nostr_event::Event;
+    use crate::nostr::nostr_event::UnsignedEvent;
+
+    fn recipient() -> XOnlyPublicKey {
+        XOnlyPublicKey::from_str("7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e")
+            .unwrap()
+    }
+
+    fn zap_request(kind: u16, tags: Vec<Vec<&str>>) -> Event {
+        let sec_key =
+            SecretKey::from_str("d7bee682d987439fae91bdc5fed8bbf16d84ec077a2bd5cf7592e384668198f3")
+                .unwrap();
+
+        UnsignedEvent {
+            pubkey: sec_key.x_only_public_key(&Secp256k1::new()).0.to_string(),
+            created_at: 0,
+            kind,
+            tags: tags
+                .into_iter()
+                .map(|tag| tag.into_iter().map(Into::into).collect())
+                .collect(),
+            content: "".into(),
+        }
+        .into_signed(&sec_key)
+    }
+
+    #[test]
+    fn test_validate_zap_request() {
+        let p_tag = recipient().to_string();
+        let valid = zap_request(
+            9734,
+            vec![
+                vec!["p", &p_tag],
+                vec!["amount", "21000"],
+                vec!["relays", "wss://relay.damus.io"],
+            ],
+        );
+        assert!(validate_zap_request(&valid, &recipient(), 21_000).is_ok());
+        assert_eq!(
+            validate_zap_request(&valid, &recipient(), 1_000),
+            Err(ZapRequestError::AmountMismatch)
+        );
+
+        let mut tampered = valid.clone();
+        tampered.content = "tampered".into();
+        assert_eq!(
+            validate_zap_request(&tampered, &recipient(), 21_000),
+            Err(ZapRequestError::InvalidSignature)
+        );
+
+        let wrong_kind = zap_request(1, vec![vec!["p", &p_tag], vec!["relays", "wss://a"]]);
+        assert_eq!(
+            validate_zap_request(&wrong_kind, &recipient(), 21_000),
+            Err(ZapRequestError::WrongKind)
+        );
+
+        let two_ps = zap_request(
+            9734,
+            vec![
+                vec!["p", &p_tag],
+                vec!["p", &p_tag],
+                vec!["relays", "wss://a"],
+            ],
+        );
+        assert_eq!(
+            validate_zap_request(&two_ps, &recipient(), 21_000),
+            Err(ZapRequestError::InvalidRecipientTag)
+        );
+
+        let no_relays = zap_request(9734, vec![vec!["p", &p_tag]]);
+        assert_eq!(
+            validate_zap_request(&no_relays, &recipient(), 21_000),
+            Err(ZapRequestError::MissingRelays)
+        );
+
+        let empty_tag = zap_request(
+            9734,
+            vec![vec!["p", &p_tag], vec![], vec!["relays", "wss://a"]],
+        );
+        assert_eq!(
+            validate_zap_request(&empty_tag, &recipient(), 21_000),
+            Err(ZapRequestError::EmptyTag)
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use notify::RecursiveMode;
+use notify::Watcher;
+use tokio::select;
+use tokio::signal::unix::signal;
+use tokio::signal::unix::SignalKind;
+use tokio::sync::mpsc::channel;
+use tokio::time::sleep;
+
+use super::UserData;
+use super::UserStore;
+use super::UserStoreError;
+use crate::api::lnaddress::validate_username;
+
+/// How long we wait after a change in our dir before reloading it. Editors usually touch a file
+/// more than once when saving it, so this avoids reloading for each of them
+const RELOAD_DELAY: Duration = Duration::from_millis(200);
+
+/// Keeps users in a directory, with one json file per user, named after them
+///
+/// Users are loaded into memory, and reloaded every time something changes in the directory, or
+/// if we get a SIGHUP.
+pub struct DirUserStore {
+    /// The directory with our users
+    dir: String,
+    /// Every valid user we found in our dir
+    users: RwLock<HashMap<String, UserData>>,
+}
+
+impl DirUserStore {
+    pub fn new(dir: String) -> Self {
+        let store = Self {
+            dir,
+            users: RwLock::default(),
+        };
+        store.reload();
+
+        store
+    }
+
+    /// Reads a user file, making sure it's something we can serve
+    fn load_user(path: &Path) -> Result<UserData, UserStoreError> {
+        let user: UserData = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        user.validate()?;
+
+        Ok(user)
+    }
+
+    /// Reads every user from our dir again. Invalid users are logged and skipped
+    pub fn reload(&self) {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                // keep what we have, this may be temporary
+                println!("can't read users dir {}: {e}", self.dir);
+                return;
+            }
+        };
+
+        let mut users = HashMap::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+
+            let Some(name) = entry.file_name().to_str().map(ToOwned::to_owned) else {
+                println!("ignoring {path:?}: invalid username");
+                continue;
+            };
+
+            if validate_username(&name).is_err() {
+                println!("ignoring {path:?}: invalid username");
+                continue;
+            }
+
+            match Self::load_user(&path) {
+                Ok(user) => {
+                    users.insert(name, user);
+                }
+                Err(e) => println!("ignoring {path:?}: {e}"),
+            }
+        }
+
+        println!("loaded {} users from {}", users.len(), self.dir);
+        *self.users.write().expect("lock poisoned") = users;
+    }
+
+    /// Reloads our users every time something changes in our dir, or when we get a SIGHUP
+    pub async fn watch(self: Arc<Self>) {
+        let (sender, mut changes) = channel(1);
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.is_ok() {
+                // if the channel is full, a reload is already coming
+                let _ = sender.try_send(());
+            }
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(Path::new(&self.dir), RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+
+        // the watcher stops once dropped, so keep it around
+        let _watcher = match watcher {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                println!("can't watch users dir, we'll only reload it on SIGHUP: {e}");
+                None
+            }
+        };
+
+        let mut hangup = signal(SignalKind::hangup()).expect("can't listen for SIGHUP");
+
+        loop {
+            select! {
+                Some(()) = changes.recv() => {
+                    sleep(RELOAD_DELAY).await;
+                    while changes.try_recv().is_ok() {}
+                }
+
+                Some(()) = hangup.recv() => {}
+
+                else => return,
+            }
+
+            self.reload();
+        }
+    }
+}
+
+impl UserStore for DirUserStore {
+    fn get(&self, username: &str) -> Result<Option<UserData>, UserStoreError> {
+        Ok(self
+            .users
+            .read()
+            .expect("lock poisoned")
+            .get(username)
+            .cloned())
+    }
+
+    fn list(&self) -> Result<Vec<(String, UserData)>, UserStoreError> {
+        let mut users: Vec<(String, UserData)> = self
+            .users
+            .read()
+            .expect("lock poisoned")
+            .iter()
+            .map(|(username, user)| (username.clone(), user.clone()))
+            .collect();
+        users.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(users)
+    }
+
+    fn insert(&self, username: &str, user: &UserData) -> Result<bool, UserStoreError> {
+        // holding the lock keeps other writes out, and hard links never replace an existing
+        // file, so we don't overwrite a user someone dropped in our dir either
+        let mut users = self.users.write().expect("lock poisoned");
+        if users.contains_key(username) {
+            return Ok(false);
+        }
+
+        let path = format!("{}/{username}", self.dir);
+        let temp = format!("{}/.{username}.tmp", self.dir);
+        std::fs::write(&temp, serde_json::to_string_pretty(user)?)?;
+        let linked = std::fs::hard_link(&temp, &path);
+        std::fs::remove_file(&temp)?;
+        match linked {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+
+        users.insert(username.to_owned(), user.clone());
+        Ok(true)
+    }
+
+    fn put(&self, username: &str, user: &UserData) -> Result<(), UserStoreError> {
+        // like in insert, the lock keeps other writes from using our temporary file while we
+        // still need it
+        let mut users = self.users.write().expect("lock poisoned");
+
+        // write to a temporary file first, so we never load a half-written user. Its name isn't
+        // a valid username, so reloads ignore it
+        let path = format!("{}/{username}", self.dir);
+        let temp = format!("{}/.{username}.tmp", self.dir);
+        std::fs::write(&temp, serde_json::to_string_pretty(user)?)?;
+        std::fs::rename(&temp, &path)?;
+
+        users.insert(username.to_owned(), user.clone());
+        Ok(())
+    }
+
+    fn delete(&self, username: &str) -> Result<bool, UserStoreError> {
+        let mut users = self.users.write().expect("lock poisoned");
+        match std::fs::remove_file(format!("{}/{username}", self.dir)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(users.remove(username).is_some())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DirUserStore;
+    use super::UserStore;
+
+    #[test]
+    fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("ln-address-users-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("john"),
+            r#"{"metadata": "[[\"text/plain\", \"john\"]]"}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("broken"), "not json").unwrap();
+        std::fs::write(dir.join("nometa"), r#"{"metadata": "[]"}"#).unwrap();
+        std::fs::write(dir.join(".john.swp"), r#"{"metadata": "[]"}"#).unwrap();
+
+        let store = DirUserStore::new(dir.to_str().unwrap().to_owned());
+        let john = store.get("john").unwrap().unwrap();
+        assert_eq!(john.metadata, r#"[["text/plain", "john"]]"#);
+        assert!(john.nostr_pubkey().is_none());
+        assert!(store.get("mary").unwrap().is_none());
+        assert!(store.get("broken").unwrap().is_none());
+        assert!(store.get("nometa").unwrap().is_none());
+
+        std::fs::write(
+            dir.join("mary"),
+            r#"{"metadata": "[[\"text/plain\", \"mary\"]]"}"#,
+        )
+        .unwrap();
+        std::fs::remove_file(dir.join("john")).unwrap();
+        assert!(store.get("mary").unwrap().is_none());
+
+        store.reload();
+        assert!(store.get("mary").unwrap().is_some());
+        assert!(store.get("john").unwrap().is_none());
+        let names: Vec<String> = store
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["mary".to_string()]);
+
+        let mary = store.get("mary").unwrap().unwrap();
+        assert!(!store.insert("mary", &mary).unwrap());
+        assert!(store.insert("john", &mary).unwrap());
+        assert!(!store.insert("john", &mary).unwrap());
+        store.put("john", &mary).unwrap();
+        assert!(store.get("john").unwrap().is_some());
+
+        // concurrent writes to the same user share a temporary file, they must not trip on it
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..25 {
+                        store.put("john", &mary).unwrap();
+                    }
+                });
+            }
+        });
+        assert!(DirUserStore::load_user(&dir.join("john")).is_ok());
+        assert!(store.delete("mary").unwrap());
+        assert!(!store.delete("mary").unwrap());
+
+        store.reload();
+        assert!(store.get("john").unwrap().is_some());
+        assert!(store.get("mary").unwrap().is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
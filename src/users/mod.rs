@@ -0,0 +1,373 @@
+//! Where we find the users we serve lightning addresses for
+//!
+//! Users can either live in a directory, with one json file per user, or in our SQLite database.
+//! Both keep the same [UserData], so moving from one to the other is just a matter of copying
+//! the json around.
+
+pub mod dir;
+pub mod sqlite;
+
+use std::fmt::Display;
+
+use secp256k1::XOnlyPublicKey;
+use serde_json::Value;
+
+use crate::nostr::nip19::parse_pubkey;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct UserData {
+    /// A stringfyed json with some metadata about ourselves
+    pub metadata: String,
+    /// This user's nostr public key, either hex-encoded or as an npub.
+    ///
+    /// Zaps are only allowed for users that have one
+    #[serde(
+        rename = "nostrPubkey",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub nostr_pubkey: Option<String>,
+    /// How long the comments sent with a payment can be (LUD-12). Zero means no comments
+    #[serde(rename = "commentAllowed", default, skip_serializing_if = "is_zero")]
+    pub comment_allowed: u32,
+    /// Which information payers should send about themselves (LUD-18)
+    #[serde(rename = "payerData", default, skip_serializing_if = "Option::is_none")]
+    pub payer_data: Option<PayerDataSettings>,
+    /// What the payer's wallet should show after paying (LUD-09)
+    #[serde(
+        rename = "successAction",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub success_action: Option<SuccessAction>,
+    /// The smallest amount this user can receive, in milisats. Defaults to our global limit. It
+    /// must be a whole number of sats, since some backends can't create invoices for anything
+    /// else
+    #[serde(
+        rename = "minSendable",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_sendable: Option<u64>,
+    /// The biggest amount this user can receive, in milisats. Defaults to our global limit
+    #[serde(
+        rename = "maxSendable",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_sendable: Option<u64>,
+}
+
+/// How long success action messages and descriptions can be (LUD-09)
+const MAX_SUCCESS_ACTION_TEXT: usize = 144;
+
+/// How long the secret in an aes success action can be (LUD-10)
+const MAX_SUCCESS_ACTION_SECRET: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "tag", rename_all = "lowercase")]
+/// Something shown to the payer after they pay (LUD-09)
+pub enum SuccessAction {
+    /// Just a message
+    Message { message: String },
+    /// A link, with a description of what it is
+    Url { description: String, url: String },
+    /// A secret that is encrypted with the invoice's preimage, so only the payer can read it
+    /// once they pay (LUD-10)
+    Aes { description: String, secret: String },
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+/// Whether we want a given payer data field
+pub struct PayerDataField {
+    /// If true, payments without this field are rejected
+    pub mandatory: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+/// The payer data fields a user wants. Fields that are None aren't requested at all
+pub struct PayerDataSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<PayerDataField>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<PayerDataField>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<PayerDataField>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<PayerDataField>,
+    /// A LUD-04 signature from the payer's linking key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<PayerDataField>,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+impl UserData {
+    /// Returns this user's nostr public key, if they have a valid one
+    pub fn nostr_pubkey(&self) -> Option<XOnlyPublicKey> {
+        self.nostr_pubkey.as_deref().and_then(parse_pubkey)
+    }
+
+    /// Checks whether this is something we can serve
+    ///
+    /// Wallets expect the metadata to be a json array of arrays, with at least a text/plain
+    /// entry (LUD-06)
+    pub fn validate(&self) -> Result<(), UserStoreError> {
+        let metadata: Vec<Vec<Value>> = serde_json::from_str(&self.metadata)?;
+        let has_text = metadata.iter().any(|entry| {
+            entry.first().and_then(Value::as_str) == Some("text/plain")
+                && entry.get(1).is_some_and(Value::is_string)
+        });
+
+        if !has_text {
+            return Err(UserStoreError::InvalidMetadata);
+        }
+
+        if self.nostr_pubkey.is_some() && self.nostr_pubkey().is_none() {
+            return Err(UserStoreError::InvalidNostrPubkey);
+        }
+
+        let valid_action = match &self.success_action {
+            None => true,
+            Some(SuccessAction::Message { message }) => {
+                message.chars().count() <= MAX_SUCCESS_ACTION_TEXT
+            }
+            Some(SuccessAction::Url { description, url }) => {
+                description.chars().count() <= MAX_SUCCESS_ACTION_TEXT
+                    && (url.starts_with("https://") || url.starts_with("http://"))
+            }
+            Some(SuccessAction::Aes {
+                description,
+                secret,
+            }) => {
+                description.chars().count() <= MAX_SUCCESS_ACTION_TEXT
+                    && secret.len() <= MAX_SUCCESS_ACTION_SECRET
+            }
+        };
+
+        if !valid_action {
+            return Err(UserStoreError::InvalidSuccessAction);
+        }
+
+        if self
+            .min_sendable
+            .is_some_and(|min| min == 0 || min % 1000 != 0)
+        {
+            return Err(UserStoreError::InvalidSendable);
+        }
+
+        if let (Some(min), Some(max)) = (self.min_sendable, self.max_sendable) {
+            if min > max {
+                return Err(UserStoreError::InvalidSendable);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the smallest and biggest amounts this user can receive, in milisats, falling back
+    /// to our global limits for the ones it doesn't set
+    ///
+    /// The user's limits may be fine on their own but not together with ours, e.g. a
+    /// maxSendable below our global minimum
+    pub fn sendable(
+        &self,
+        default_min: u64,
+        default_max: u64,
+    ) -> Result<(u64, u64), UserStoreError> {
+        let min = self.min_sendable.unwrap_or(default_min);
+        let max = self.max_sendable.unwrap_or(default_max);
+        if min > max {
+            return Err(UserStoreError::InvalidSendable);
+        }
+
+        Ok((min, max))
+    }
+}
+
+#[derive(Debug)]
+/// The errors we may find while loading a user
+pub enum UserStoreError {
+    /// We couldn't read from the users dir
+    Io(std::io::Error),
+    /// We couldn't read from the database
+    Database(rusqlite::Error),
+    /// We found this user, but its data is invalid
+    InvalidUser(serde_json::Error),
+    /// This user's metadata doesn't have a text/plain entry
+    InvalidMetadata,
+    /// This user's nostr pubkey isn't a valid hex key or npub
+    InvalidNostrPubkey,
+    /// This user's success action has texts that are too long, or an invalid url
+    InvalidSuccessAction,
+    /// This user's minSendable isn't a positive number of whole sats, or is bigger than its
+    /// maxSendable
+    InvalidSendable,
+}
+
+impl Display for UserStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserStoreError::Io(e) => write!(f, "can't read user: {e}"),
+            UserStoreError::Database(e) => write!(f, "database error: {e}"),
+            UserStoreError::InvalidUser(e) => write!(f, "invalid user data: {e}"),
+            UserStoreError::InvalidMetadata => {
+                write!(f, "metadata must be a json array with a text/plain entry")
+            }
+            UserStoreError::InvalidNostrPubkey => write!(f, "invalid nostr pubkey"),
+            UserStoreError::InvalidSuccessAction => write!(
+                f,
+                "success action texts must have at most 144 chars, secrets 4096 bytes, and urls \
+                 must be http(s)"
+            ),
+            UserStoreError::InvalidSendable => {
+                write!(
+                    f,
+                    "minSendable must be a positive multiple of 1000 and can't be bigger than \
+                     maxSendable"
+                )
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for UserStoreError {
+    fn from(value: std::io::Error) -> Self {
+        UserStoreError::Io(value)
+    }
+}
+
+impl From<rusqlite::Error> for UserStoreError {
+    fn from(value: rusqlite::Error) -> Self {
+        UserStoreError::Database(value)
+    }
+}
+
+impl From<serde_json::Error> for UserStoreError {
+    fn from(value: serde_json::Error) -> Self {
+        UserStoreError::InvalidUser(value)
+    }
+}
+
+/// Something that knows our users
+///
+/// Usernames are validated before getting here, so implementations may use them as file names
+pub trait UserStore: Send + Sync {
+    /// Returns the data for `username`, or None if we don't have such user
+    fn get(&self, username: &str) -> Result<Option<UserData>, UserStoreError>;
+
+    /// Returns every user we have, sorted by username
+    fn list(&self) -> Result<Vec<(String, UserData)>, UserStoreError>;
+
+    /// Creates a user, unless it already exists. Returns whether it was created
+    ///
+    /// This must be atomic, so two concurrent inserts for the same name can't both succeed
+    fn insert(&self, username: &str, user: &UserData) -> Result<bool, UserStoreError>;
+
+    /// Creates or replaces a user
+    fn put(&self, username: &str, user: &UserData) -> Result<(), UserStoreError>;
+
+    /// Removes a user, returning whether it existed
+    fn delete(&self, username: &str) -> Result<bool, UserStoreError>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::SuccessAction;
+    use super::UserData;
+    use super::UserStoreError;
+
+    fn user(metadata: &str, nostr_pubkey: Option<&str>) -> UserData {
+        UserData {
+            metadata: metadata.into(),
+            nostr_pubkey: nostr_pubkey.map(Into::into),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(user(r#"[["text/plain", "john"]]"#, None).validate().is_ok());
+        assert!(user(
+            r#"[["text/identifier", "john@smith.com"], ["text/plain", "john"]]"#,
+            Some("npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg")
+        )
+        .validate()
+        .is_ok());
+
+        assert!(matches!(
+            user("text/plain", None).validate(),
+            Err(UserStoreError::InvalidUser(_))
+        ));
+        assert!(matches!(
+            user(r#"["text/plain", "john"]"#, None).validate(),
+            Err(UserStoreError::InvalidUser(_))
+        ));
+        assert!(matches!(
+            user(r#"[["image/png;base64", "AAAA"]]"#, None).validate(),
+            Err(UserStoreError::InvalidMetadata)
+        ));
+        assert!(matches!(
+            user(r#"[["text/plain", "john"]]"#, Some("npub1")).validate(),
+            Err(UserStoreError::InvalidNostrPubkey)
+        ));
+
+        let mut with_action = user(r#"[["text/plain", "john"]]"#, None);
+        with_action.success_action = Some(SuccessAction::Url {
+            description: "your ebook".into(),
+            url: "https://smith.com/ebook".into(),
+        });
+        assert!(with_action.validate().is_ok());
+
+        with_action.success_action = Some(SuccessAction::Url {
+            description: "your ebook".into(),
+            url: "javascript:alert(1)".into(),
+        });
+        assert!(matches!(
+            with_action.validate(),
+            Err(UserStoreError::InvalidSuccessAction)
+        ));
+
+        with_action.success_action = Some(SuccessAction::Message {
+            message: "a".repeat(145),
+        });
+        assert!(matches!(
+            with_action.validate(),
+            Err(UserStoreError::InvalidSuccessAction)
+        ));
+
+        let mut limited = user(r#"[["text/plain", "john"]]"#, None);
+        limited.min_sendable = Some(10_000);
+        limited.max_sendable = Some(1_000);
+        assert!(matches!(
+            limited.validate(),
+            Err(UserStoreError::InvalidSendable)
+        ));
+
+        limited.max_sendable = None;
+        assert!(limited.validate().is_ok());
+        assert_eq!(limited.sendable(1_000, 20_000).unwrap(), (10_000, 20_000));
+        assert!(matches!(
+            limited.sendable(1_000, 5_000),
+            Err(UserStoreError::InvalidSendable)
+        ));
+
+        limited.min_sendable = None;
+        limited.max_sendable = Some(1_000);
+        assert!(limited.validate().is_ok());
+        assert!(matches!(
+            limited.sendable(2_000, 20_000),
+            Err(UserStoreError::InvalidSendable)
+        ));
+
+        for min in [0, 1_500] {
+            limited.min_sendable = Some(min);
+            assert!(matches!(
+                limited.validate(),
+                Err(UserStoreError::InvalidSendable)
+            ));
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use super::UserData;
+use super::UserStore;
+use super::UserStoreError;
+use crate::db::Database;
+
+/// Keeps users in the users table of our database
+///
+/// Each user is stored as the same json we'd have in a users dir, so new settings don't need a
+/// new column.
+pub struct SqliteUserStore {
+    db: Database,
+}
+
+impl SqliteUserStore {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Reads a user row, making sure it's something we can serve. Rows may have been written
+    /// with plain SQL, so we can't trust them more than a users dir
+    fn parse_user(username: &str, user: &str) -> Option<UserData> {
+        let user = serde_json::from_str::<UserData>(user)
+            .map_err(UserStoreError::from)
+            .and_then(|user| user.validate().map(|_| user));
+
+        match user {
+            Ok(user) => Some(user),
+            Err(e) => {
+                println!("ignoring user {username}: {e}");
+                None
+            }
+        }
+    }
+}
+
+impl UserStore for SqliteUserStore {
+    fn get(&self, username: &str) -> Result<Option<UserData>, UserStoreError> {
+        let user = self.db.get_user(username)?;
+
+        Ok(user.and_then(|user| Self::parse_user(username, &user)))
+    }
+
+    fn list(&self) -> Result<Vec<(String, UserData)>, UserStoreError> {
+        let users = self
+            .db
+            .list_users()?
+            .into_iter()
+            .filter_map(|(username, user)| {
+                Self::parse_user(&username, &user).map(|user| (username, user))
+            })
+            .collect();
+
+        Ok(users)
+    }
+
+    fn insert(&self, username: &str, user: &UserData) -> Result<bool, UserStoreError> {
+        Ok(self
+            .db
+            .insert_user(username, &serde_json::to_string(user)?)?)
+    }
+
+    fn put(&self, username: &str, user: &UserData) -> Result<(), UserStoreError> {
+        self.db.put_user(username, &serde_json::to_string(user)?)?;
+
+        Ok(())
+    }
+
+    fn delete(&self, username: &str) -> Result<bool, UserStoreError> {
+        Ok(self.db.delete_user(username)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SqliteUserStore;
+    use super::UserData;
+    use super::UserStore;
+    use crate::db::Database;
+
+    #[test]
+    fn test_get() {
+        let store = SqliteUserStore::new(Database::open(":memory:").unwrap());
+        let user = UserData {
+            metadata: r#"[["text/plain", "john"]]"#.into(),
+            nostr_pubkey: Some(
+                "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg".into(),
+            ),
+            ..Default::default()
+        };
+        store.put("john", &user).unwrap();
+
+        let john = store.get("john").unwrap().unwrap();
+        assert_eq!(john.metadata, r#"[["text/plain", "john"]]"#);
+        assert!(john.nostr_pubkey().is_some());
+        assert!(store.get("mary").unwrap().is_none());
+
+        assert!(store.insert("alice", &user).unwrap());
+        assert!(!store.insert("alice", &UserData::default()).unwrap());
+        assert!(store.get("alice").unwrap().is_some());
+
+        // rows written by hand are checked like files in a users dir
+        store.put("bob", &UserData::default()).unwrap();
+        assert!(store.get("bob").unwrap().is_none());
+        let names: Vec<String> = store
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["alice".to_string(), "john".to_string()]);
+
+        assert!(store.delete("john").unwrap());
+        assert!(!store.delete("john").unwrap());
+        assert!(store.get("john").unwrap().is_none());
+    }
+}